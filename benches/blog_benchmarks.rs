@@ -1,12 +1,18 @@
+use axum::Router;
+use axum::body::Body;
+use axum::http::Request;
 use axum::serve;
-use blog_engine::create_app_with_dirs;
-use criterion::{Criterion, criterion_group, criterion_main};
+use blog_engine::{
+    BlogConfig, FileSystemBlogRepository, create_app_with_dirs, create_app_with_repository,
+};
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
 use std::fs;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tempfile::TempDir;
 use tokio::sync::oneshot;
+use tower::Service;
 
 struct TestServer {
     addr: SocketAddr,
@@ -18,6 +24,10 @@ impl TestServer {
     async fn new(content_dir: TempDir) -> Self {
         let content_dir_buf: PathBuf = content_dir.path().into();
         let app = create_app_with_dirs(content_dir_buf, ".".into());
+        Self::serve(app, content_dir).await
+    }
+
+    async fn serve(app: Router, content_dir: TempDir) -> Self {
         let addr = SocketAddr::from(([127, 0, 0, 1], 0));
         let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
         let server_addr = listener.local_addr().unwrap();
@@ -131,5 +141,134 @@ fn benchmark_blog(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, benchmark_blog);
+// With the html cache disabled every request goes through the repository,
+// which shows the cost of slug lookups and listing on a large archive.
+fn benchmark_repository_lookups(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let setup = || {
+        let temp_dir = setup_test_environment(5_000);
+        fs::write(
+            temp_dir.path().join("blog_config.yaml"),
+            "site_title: Bench\nsite_description: Bench\ncache:\n  max_entries: 0\n  preload: false\n",
+        )
+        .unwrap();
+        temp_dir
+    };
+    // The baseline reads every post from disk on each lookup, the default
+    // app serves them from its in-memory index.
+    let baseline = runtime.block_on(async {
+        let temp_dir = setup();
+        let config = BlogConfig::from_file_or_default(temp_dir.path().join("blog_config.yaml"));
+        let repo = FileSystemBlogRepository::new(temp_dir.path().into());
+        TestServer::serve(create_app_with_repository(repo, ".", config), temp_dir).await
+    });
+    let indexed = runtime.block_on(TestServer::new(setup()));
+
+    let client = reqwest::Client::new();
+    let mut group = c.benchmark_group("repository_lookups");
+
+    for (name, server) in [("baseline", &baseline), ("indexed", &indexed)] {
+        group.bench_function(
+            BenchmarkId::new("find_post_by_slug_uncached_5k", name),
+            |b| {
+                b.to_async(&runtime).iter(|| async {
+                    let response = client.get(server.url("/post-2500")).send().await.unwrap();
+                    assert!(response.status().is_success());
+                });
+            },
+        );
+
+        group.bench_function(BenchmarkId::new("get_all_posts_uncached_5k", name), |b| {
+            b.to_async(&runtime).iter(|| async {
+                let response = client.get(server.url("/")).send().await.unwrap();
+                assert!(response.status().is_success());
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// A few posts with code blocks, so startup and the first render include
+// loading the syntax highlighting assets and compiling their regexes.
+fn setup_code_environment() -> TempDir {
+    let temp_dir = setup_test_environment(10);
+    let code = "```rust\nfn main() {\n    println!(\"Hello\");\n}\n```\n\n\
+                ```python\ndef main():\n    print(\"Hello\")\n```\n\n";
+    fs::write(
+        temp_dir.path().join("posts/code-post.md"),
+        format!(
+            "---\ntitle: Code Post\nslug: code-post\n---\n{}",
+            code.repeat(20)
+        ),
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("blog_config.yaml"),
+        "site_title: Bench\nsite_description: Bench\ncache:\n  max_entries: 0\n  preload: false\n",
+    )
+    .unwrap();
+    temp_dir
+}
+
+// Each iteration gets its own runtime, dropped with the app outside the
+// measurement, so tasks spawned by one app never run during the next.
+fn fresh_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+// Building the app is what every server start and every reload pays for.
+fn benchmark_startup(c: &mut Criterion) {
+    let temp_dir = setup_code_environment();
+    let mut group = c.benchmark_group("startup");
+
+    group.bench_function("create_app", |b| {
+        b.iter_batched(
+            fresh_runtime,
+            |runtime| {
+                let app = runtime.block_on(async {
+                    create_app_with_dirs(PathBuf::from(temp_dir.path()), ".".into())
+                });
+                (app, runtime)
+            },
+            BatchSize::PerIteration,
+        );
+    });
+
+    // The first request of a fresh app, with highlighted code blocks.
+    group.bench_function("first_render_with_code", |b| {
+        b.iter_batched(
+            fresh_runtime,
+            |runtime| {
+                runtime.block_on(async {
+                    let mut app = create_app_with_dirs(PathBuf::from(temp_dir.path()), ".".into());
+                    let response = app
+                        .call(Request::get("/code-post").body(Body::empty()).unwrap())
+                        .await
+                        .unwrap();
+                    assert!(response.status().is_success());
+                    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                        .await
+                        .unwrap();
+                    assert!(String::from_utf8_lossy(&body).contains("Code Post"));
+                });
+                runtime
+            },
+            BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    benchmark_startup,
+    benchmark_blog,
+    benchmark_repository_lookups
+);
 criterion_main!(benches);
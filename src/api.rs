@@ -0,0 +1,315 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::Markdown;
+use crate::model::parse_date_for_sorting;
+use crate::renderer::Renderer;
+use crate::slug::Slug;
+
+// Every post on a page is rendered, so pages stay small.
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+// A post or page as served by the JSON API.
+#[derive(Serialize, Debug)]
+pub struct ContentJson {
+    pub title: Option<String>,
+    pub slug: String,
+    pub slugs: Vec<String>,
+    // As YYYY-MM-DD.
+    pub publish_date: Option<String>,
+    // RFC 3339
+    pub modified: Option<String>,
+    pub updated: Option<String>,
+    pub front_matter: Option<serde_json::Value>,
+    pub markdown: String,
+    pub html: String,
+}
+
+impl ContentJson {
+    pub(crate) fn new(markdown: Markdown, html: String) -> Self {
+        ContentJson {
+            slug: markdown.primary_slug(),
+            title: markdown.title,
+            slugs: markdown.slugs,
+            publish_date: markdown.publish_date.map(|date| date.to_string()),
+            modified: markdown.modified.map(|date| date.to_rfc3339()),
+            updated: markdown.updated.map(|date| date.to_rfc3339()),
+            front_matter: markdown.front_matter,
+            markdown: markdown.content,
+            html,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PostList {
+    pub posts: Vec<ContentJson>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+    pub total_pages: usize,
+}
+
+// Filters and pagination of the post list. Pages start at 1, `q` matches
+// titles ignoring case and `from`/`to` are inclusive publish dates.
+#[derive(Deserialize, Debug, Default)]
+pub struct PostQuery {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    pub q: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+// One page of posts, with the total number of posts that matched.
+pub(crate) struct Selection {
+    pub posts: Vec<Markdown>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+}
+
+impl PostQuery {
+    pub(crate) fn select(&self, posts: &[Markdown]) -> Result<Selection, StatusCode> {
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page == 0 || per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let from = Self::date(self.from.as_deref())?;
+        let to = Self::date(self.to.as_deref())?;
+        let q = self.q.as_deref().map(str::to_lowercase);
+
+        let matching: Vec<&Markdown> = posts
+            .iter()
+            .filter(|post| match &q {
+                Some(q) => post
+                    .title
+                    .as_deref()
+                    .is_some_and(|title| title.to_lowercase().contains(q)),
+                None => true,
+            })
+            .filter(|post| match (from, to) {
+                (None, None) => true,
+                _ => post.publish_date.is_some_and(|date| {
+                    from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to)
+                }),
+            })
+            .collect();
+        let total = matching.len();
+        let posts = matching
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .cloned()
+            .collect();
+
+        Ok(Selection {
+            posts,
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    // Only pages of the whole list in the default size are cached.
+    pub(crate) fn cache_key(&self) -> Option<String> {
+        let unfiltered = self.q.is_none() && self.from.is_none() && self.to.is_none();
+        let default_size = self.per_page.is_none_or(|size| size == DEFAULT_PER_PAGE);
+        (unfiltered && default_size).then(|| format!("posts_json:{}", self.page.unwrap_or(1)))
+    }
+
+    fn date(date: Option<&str>) -> Result<Option<chrono::NaiveDate>, StatusCode> {
+        match date {
+            Some(date) => parse_date_for_sorting(date)
+                .map(Some)
+                .ok_or(StatusCode::BAD_REQUEST),
+            None => Ok(None),
+        }
+    }
+}
+
+impl PostList {
+    pub(crate) fn new(selection: Selection, posts: Vec<ContentJson>) -> Self {
+        PostList {
+            posts,
+            page: selection.page,
+            per_page: selection.per_page,
+            total: selection.total,
+            total_pages: selection.total.div_ceil(selection.per_page),
+        }
+    }
+}
+
+// Whether the client prefers JSON over html, judged by q-values of the
+// Accept header. Without a preference html wins, so browsers and clients
+// sending `*/*` keep getting pages.
+pub(crate) fn wants_json(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    let mut json_q: Option<f32> = None;
+    let mut html_q: Option<f32> = None;
+    for entry in accept.split(',') {
+        let mut parts = entry.split(';');
+        let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match media_type.as_str() {
+            "application/json" => json_q = Some(q),
+            "text/html" => html_q = Some(q),
+            "text/*" | "*/*" => html_q = html_q.or(Some(q)).map(|html| html.max(q)),
+            _ => {}
+        }
+    }
+
+    match json_q {
+        Some(json_q) => json_q > 0.0 && json_q > html_q.unwrap_or(0.0),
+        None => false,
+    }
+}
+
+// Html and JSON share URLs, so caches must keep them apart.
+pub(crate) fn vary_on_accept(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::VARY,
+        HeaderValue::from_static("accept, accept-encoding"),
+    );
+    response
+}
+
+pub(crate) fn negotiated_json(value: impl Serialize) -> Response {
+    vary_on_accept(Json(value).into_response())
+}
+
+pub(crate) async fn posts_handler(
+    Query(query): Query<PostQuery>,
+    renderer: Extension<Arc<dyn Renderer + Send + Sync>>,
+) -> Result<Response, StatusCode> {
+    let posts = renderer.posts_json(query).await?;
+    Ok(Json(&*posts).into_response())
+}
+
+pub(crate) async fn post_handler(
+    Path(slug): Path<Slug>,
+    renderer: Extension<Arc<dyn Renderer + Send + Sync>>,
+) -> Result<Json<ContentJson>, StatusCode> {
+    renderer.post_json(slug).await.map(Json)
+}
+
+pub(crate) async fn page_handler(
+    Path(slug): Path<Slug>,
+    renderer: Extension<Arc<dyn Renderer + Send + Sync>>,
+) -> Result<Json<ContentJson>, StatusCode> {
+    renderer.page_json(slug).await.map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn post(title: &str, date: Option<&str>) -> Markdown {
+        let front_matter = match date {
+            Some(date) => format!("---\ntitle: {title}\ndatePublished: {date}\n---\n"),
+            None => format!("---\ntitle: {title}\n---\n"),
+        };
+        Markdown::parse(&front_matter)
+    }
+
+    fn titles(selection: Selection) -> Vec<String> {
+        selection
+            .posts
+            .into_iter()
+            .filter_map(|post| post.title)
+            .collect()
+    }
+
+    #[test]
+    fn json_is_served_only_when_preferred() {
+        assert!(!wants_json(&HeaderMap::new()));
+        assert!(!wants_json(&accept("*/*")));
+        assert!(!wants_json(&accept("text/html,application/json")));
+        assert!(!wants_json(&accept("application/json;q=0.5, */*")));
+        assert!(wants_json(&accept("application/json")));
+        assert!(wants_json(&accept("application/json, */*;q=0.8")));
+        assert!(wants_json(&accept("text/html;q=0.9, application/json")));
+    }
+
+    #[test]
+    fn filters_by_title_and_date_range() {
+        let posts = vec![
+            post("Rust news", Some("2023-03-01")),
+            post("Other rust", Some("2022-01-01")),
+            post("Undated rust", None),
+            post("Cooking", Some("2023-02-01")),
+        ];
+        let query = PostQuery {
+            q: Some("RUST".to_string()),
+            from: Some("2023-01-01".to_string()),
+            ..PostQuery::default()
+        };
+
+        let selection = query.select(&posts).unwrap();
+
+        assert_eq!(selection.total, 1);
+        assert_eq!(titles(selection), vec!["Rust news"]);
+    }
+
+    #[test]
+    fn paginates_and_rejects_invalid_parameters() {
+        let posts: Vec<Markdown> = (1..=5).map(|i| post(&format!("P{i}"), None)).collect();
+        let query = PostQuery {
+            page: Some(3),
+            per_page: Some(2),
+            ..PostQuery::default()
+        };
+
+        let selection = query.select(&posts).unwrap();
+        assert_eq!(selection.total, 5);
+        assert_eq!(titles(selection), vec!["P5"]);
+        let largest = PostQuery {
+            per_page: Some(MAX_PER_PAGE),
+            ..PostQuery::default()
+        };
+        assert_eq!(largest.select(&posts).unwrap().total, 5);
+
+        for invalid in [
+            PostQuery {
+                page: Some(0),
+                ..PostQuery::default()
+            },
+            PostQuery {
+                per_page: Some(MAX_PER_PAGE + 1),
+                ..PostQuery::default()
+            },
+            PostQuery {
+                per_page: Some(0),
+                ..PostQuery::default()
+            },
+            PostQuery {
+                to: Some("not a date".to_string()),
+                ..PostQuery::default()
+            },
+        ] {
+            assert_eq!(invalid.select(&posts).err(), Some(StatusCode::BAD_REQUEST));
+        }
+    }
+}
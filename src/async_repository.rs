@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::Markdown;
+use crate::blog_repository::{BlogRepository, RepositoryError};
+use crate::model::Revision;
+use crate::slug::Slug;
+
+// Repository used by the request handlers. Implementations must not block
+// the async runtime while doing I/O.
+#[async_trait]
+pub trait AsyncBlogRepository {
+    async fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError>;
+    async fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError>;
+    async fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError>;
+
+    async fn post_history(&self, _slug: &Slug) -> Result<Option<Vec<Revision>>, RepositoryError> {
+        Ok(None)
+    }
+
+    async fn post_diff(
+        &self,
+        _slug: &Slug,
+        _from: &str,
+        _to: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        Ok(None)
+    }
+}
+
+// Runs a synchronous repository on Tokio's blocking thread pool.
+pub struct BlockingRepository<R> {
+    inner: Arc<R>,
+}
+
+impl<R> BlockingRepository<R> {
+    pub fn new(inner: R) -> Self {
+        BlockingRepository {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+#[async_trait]
+impl<R> AsyncBlogRepository for BlockingRepository<R>
+where
+    R: BlogRepository + Send + Sync + 'static,
+{
+    async fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        let repo = self.inner.clone();
+        run_blocking(move || repo.get_all_posts()).await
+    }
+
+    async fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let repo = self.inner.clone();
+        let slug = slug.clone();
+        run_blocking(move || repo.find_post_by_slug(&slug)).await
+    }
+
+    async fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let repo = self.inner.clone();
+        let slug = slug.clone();
+        run_blocking(move || repo.get_page(&slug)).await
+    }
+
+    async fn post_history(&self, slug: &Slug) -> Result<Option<Vec<Revision>>, RepositoryError> {
+        let repo = self.inner.clone();
+        let slug = slug.clone();
+        run_blocking(move || repo.post_history(&slug)).await
+    }
+
+    async fn post_diff(
+        &self,
+        slug: &Slug,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        let repo = self.inner.clone();
+        let (slug, from, to) = (slug.clone(), from.to_string(), to.to_string());
+        run_blocking(move || repo.post_diff(&slug, &from, &to)).await
+    }
+}
+
+async fn run_blocking<T, F>(lookup: F) -> Result<T, RepositoryError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, RepositoryError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(lookup)
+        .await
+        .map_err(|_| RepositoryError::UnexpectedError)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlogDir;
+    use crate::config::BlogConfig;
+    use crate::renderer::BlogPostHandler;
+    use crate::slug::slug;
+    use std::time::{Duration, Instant};
+
+    const LOOKUP_DELAY: Duration = Duration::from_millis(200);
+
+    struct SlowRepository;
+
+    impl BlogRepository for SlowRepository {
+        fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+            std::thread::sleep(LOOKUP_DELAY);
+            Ok(Arc::new([]))
+        }
+
+        fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+            std::thread::sleep(LOOKUP_DELAY);
+            let mut markdown = Markdown::parse("---\ntitle: Slow\n---\n");
+            markdown.slugs.push(slug.to_string());
+            Ok(Some(markdown))
+        }
+
+        fn get_page(&self, _slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+            std::thread::sleep(LOOKUP_DELAY);
+            Ok(None)
+        }
+    }
+
+    // Runs on a single-threaded runtime: a blocking lookup on the runtime
+    // thread would serialize all requests.
+    #[tokio::test]
+    async fn concurrent_requests_are_not_serialized_behind_slow_repository() {
+        let handler = Arc::new(BlogPostHandler::new(
+            BlogConfig::default(),
+            SlowRepository,
+            &BlogDir(".".into()),
+        ));
+        let requests = 8;
+
+        let started = Instant::now();
+        let renders: Vec<_> = (0..requests)
+            .map(|i| {
+                let handler = handler.clone();
+                tokio::spawn(async move { handler.render_post(slug(&format!("post-{i}"))).await })
+            })
+            .collect();
+        for render in renders {
+            assert!(render.await.unwrap().is_ok());
+        }
+
+        assert!(
+            started.elapsed() < LOOKUP_DELAY * (requests / 2),
+            "Requests took {:?}, expected them to overlap",
+            started.elapsed()
+        );
+    }
+}
@@ -12,10 +12,73 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init()
         .unwrap_or_else(|e| println!("Error configuring logger: {e}"));
 
-    let content_dir = env::var("BLOG_CONTENT_DIR").unwrap_or_else(|_| "content".to_string());
+    // BLOG_CONTENT_DIR may list several directories separated like PATH; the
+    // first one holds blog_config.yaml and wins on duplicate slugs.
+    let mut content_dirs: Vec<String> = env::var_os("BLOG_CONTENT_DIR")
+        .map(|dirs| {
+            env::split_paths(&dirs)
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    if content_dirs.is_empty() {
+        content_dirs.push("content".to_string());
+    }
+    let content_dir = content_dirs.remove(0);
     let blog_dir = env::var("BLOG_DIR").unwrap_or_else(|_| "content".to_string());
 
-    let app: Router = blog_engine::create_app_with_dirs(&content_dir, &blog_dir);
+    let mut config = blog_engine::BlogConfig::from_file_or_default(
+        blog_engine::ContentDir(content_dir.clone().into()).config_file(),
+    );
+    if let Ok(backend) = env::var("BLOG_BACKEND") {
+        config.storage.backend = backend.parse()?;
+    }
+    if let Ok(sqlite_path) = env::var("BLOG_SQLITE_PATH") {
+        config.storage.sqlite_path = sqlite_path.into();
+    }
+    config
+        .storage
+        .content_dirs
+        .extend(content_dirs.into_iter().map(Into::into));
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("check") {
+        let report =
+            blog_engine::check_blog_with_config(content_dir.as_str(), blog_dir.as_str(), config)
+                .await;
+        if args.iter().any(|arg| arg == "--json") {
+            println!("{}", report.to_json());
+        } else {
+            println!("{}", report);
+        }
+        std::process::exit(if report.has_errors() { 1 } else { 0 });
+    }
+
+    if args.first().map(String::as_str) == Some("import") {
+        let db_path = std::path::Path::new(&content_dir).join(&config.storage.sqlite_path);
+        let summary = blog_engine::import_into_sqlite(content_dir.as_ref(), db_path.as_path())?;
+        println!(
+            "Imported {} posts and {} pages into {}",
+            summary.posts,
+            summary.pages,
+            db_path.display()
+        );
+        return Ok(());
+    }
+
+    // Serve a git ref instead of the content directory when BLOG_GIT_REPO is set.
+    let app: Router = match env::var("BLOG_GIT_REPO") {
+        Ok(git_repo) => {
+            let reference = env::var("BLOG_GIT_REF").unwrap_or_else(|_| "HEAD".to_string());
+            let (app, content) =
+                blog_engine::create_app_from_git(git_repo.as_str(), &reference, &blog_dir)?;
+            check_theme_or_exit(&blog_dir, &content.config());
+            #[cfg(unix)]
+            switch_ref_on_hangup(content);
+            app
+        }
+        Err(_) => default_app(&content_dir, &blog_dir, config),
+    };
 
     let host: IpAddr = env::var("HOST")
         .unwrap_or_else(|_| "127.0.0.1".to_string())
@@ -36,3 +99,80 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// Embedded content is served unless a content directory is given explicitly.
+#[cfg(feature = "embedded")]
+fn default_app(content_dir: &str, blog_dir: &str, config: blog_engine::BlogConfig) -> Router {
+    if env::var("BLOG_CONTENT_DIR").is_ok() {
+        app_from_dirs(content_dir, blog_dir, config)
+    } else {
+        blog_engine::create_app_embedded()
+    }
+}
+
+#[cfg(not(feature = "embedded"))]
+fn default_app(content_dir: &str, blog_dir: &str, config: blog_engine::BlogConfig) -> Router {
+    app_from_dirs(content_dir, blog_dir, config)
+}
+
+fn app_from_dirs(content_dir: &str, blog_dir: &str, config: blog_engine::BlogConfig) -> Router {
+    check_theme_or_exit(blog_dir, &config);
+    blog_engine::create_app_with_config(content_dir, blog_dir, config).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    })
+}
+
+// A missing or misconfigured theme would break every page, so the server
+// does not start with one. Neither does it with broken templates in strict
+// mode; in lenient mode the renderer logs them.
+fn check_theme_or_exit(blog_dir: &str, config: &blog_engine::BlogConfig) {
+    let theme_dir = blog_engine::BlogDir(blog_dir.into());
+    if let Err(e) = blog_engine::Theme::load(&theme_dir, config) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    if config.templates.validation == blog_engine::TemplateValidation::Strict {
+        let issues = blog_engine::validate_templates(&theme_dir, config);
+        for issue in &issues {
+            eprintln!("Error: {issue}");
+        }
+        if !issues.is_empty() {
+            std::process::exit(1);
+        }
+    }
+}
+
+// SIGHUP switches to the ref written in BLOG_GIT_REF_FILE, or resolves the
+// current ref again when it is unset, and drops the cached html. Deploys send
+// it after moving the branch or choosing another ref.
+#[cfg(unix)]
+fn switch_ref_on_hangup(content: blog_engine::GitContent) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            eprintln!("Warning: SIGHUP cannot switch the git ref: {e}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            let reference = match env::var("BLOG_GIT_REF_FILE") {
+                Ok(file) => match std::fs::read_to_string(&file) {
+                    Ok(reference) => reference.trim().to_string(),
+                    Err(e) => {
+                        eprintln!("Warning: {file} could not be read: {e}");
+                        continue;
+                    }
+                },
+                Err(_) => content.reference(),
+            };
+            match content.switch_ref(&reference).await {
+                Ok(()) => println!("Serving git ref {reference}"),
+                Err(e) => eprintln!("Warning: {e}"),
+            }
+        }
+    });
+}
@@ -9,6 +9,9 @@ async fn main() -> shuttle_axum::ShuttleAxum {
         // in Shuttle environment logger is preconfigured
         .unwrap_or_else(|e| println!("Error configuring logger: {e}"));
 
+    #[cfg(feature = "embedded")]
+    let app = blog_engine::create_app_embedded();
+    #[cfg(not(feature = "embedded"))]
     let app = blog_engine::create_app_with_defaults();
     Ok(app.into())
 }
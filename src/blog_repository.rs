@@ -1,20 +1,71 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::Markdown;
+use crate::model::Revision;
+use crate::slug::Slug;
 
 pub trait BlogRepository {
-    fn get_all_posts(&self) -> Result<Vec<Markdown>, RepositoryError>;
-    fn find_post_by_slug(&self, slug: &str) -> Result<Option<Markdown>, RepositoryError>;
-    fn get_page(&self, slug: &str) -> Result<Option<Markdown>, RepositoryError>;
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError>;
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError>;
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError>;
+
+    // Revisions of a post, newest first. None when the post is not under
+    // version control.
+    fn post_history(&self, _slug: &Slug) -> Result<Option<Vec<Revision>>, RepositoryError> {
+        Ok(None)
+    }
+
+    fn post_diff(
+        &self,
+        _slug: &Slug,
+        _from: &str,
+        _to: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        Ok(None)
+    }
+
+    // Repositories whose content changes while the app runs call `changed`
+    // after each change, so rendered html is not served stale.
+    fn on_change(&self, _changed: ChangeHook) {}
 }
 
+pub type ChangeHook = Arc<dyn Fn() + Send + Sync>;
+
 #[derive(Debug)]
 pub enum RepositoryError {
     NotFound,
     UnexpectedError,
 }
 
-pub(crate) struct FileSystemBlogRepository {
+// A markdown file as found on disk, before parsing.
+pub(crate) struct SourceFile {
+    pub path: PathBuf,
+    pub content: String,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SourceFile {
+    pub fn slug(&self) -> String {
+        slug_of(&self.path)
+    }
+
+    pub fn into_markdown(self) -> Markdown {
+        let mut markdown = Markdown::parse(&self.content);
+        markdown.slugs.push(self.slug());
+        markdown.modified = self.modified;
+        markdown
+    }
+}
+
+// A markdown file path with its modification time, without reading the file.
+pub(crate) struct SourceEntry {
+    pub path: PathBuf,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Reads posts from `posts/` and pages from `pages/` below the content directory.
+pub struct FileSystemBlogRepository {
     content_dir: PathBuf,
 }
 
@@ -31,75 +82,80 @@ impl FileSystemBlogRepository {
         self.content_dir.join("pages")
     }
 
-    fn all_posts_unsorted(&self) -> Result<Vec<Markdown>, RepositoryError> {
-        let posts_dir = self.posts_dir();
-        if !posts_dir.exists() {
-            return Ok(Vec::new());
-        }
-        let mut markdowns = Vec::new();
-        for entry in std::fs::read_dir(posts_dir).map_err(|_| RepositoryError::NotFound)? {
-            let entry = entry.map_err(|_| RepositoryError::UnexpectedError)?;
-            let path = entry.path();
-
-            if path.extension().map_or(true, |ext| ext != "md") {
-                continue;
-            }
-
-            let content = read_to_string(path.clone())?;
-            let mut markdown = Markdown::from_str(&content);
-            markdown.slugs.push(
-                path.file_stem()
-                    .map(|s| s.to_string_lossy().into_owned())
-                    .expect("Expected file with extention"),
-            );
+    pub(crate) fn post_files(&self) -> Result<Vec<SourceFile>, RepositoryError> {
+        markdown_files_in(self.posts_dir())
+    }
 
-            markdowns.push(markdown);
-        }
-        Ok(markdowns)
+    pub(crate) fn post_entries(&self) -> Result<Vec<SourceEntry>, RepositoryError> {
+        markdown_entries_in(self.posts_dir())
     }
-}
 
-impl BlogRepository for FileSystemBlogRepository {
-    fn get_all_posts(&self) -> Result<Vec<Markdown>, RepositoryError> {
-        let mut markdowns = self.all_posts_unsorted()?;
+    pub(crate) fn load_post(&self, entry: &SourceEntry) -> Result<Markdown, RepositoryError> {
+        let file = SourceFile {
+            path: entry.path.clone(),
+            content: read_to_string(entry.path.clone())?,
+            modified: entry.modified,
+        };
+        Ok(file.into_markdown())
+    }
 
-        fn sort_by_newest_first(
-            date_a: &chrono::NaiveDate,
-            date_b: &chrono::NaiveDate,
-        ) -> std::cmp::Ordering {
-            date_b.cmp(date_a)
-        }
+    pub(crate) fn page_files(&self) -> Result<Vec<SourceFile>, RepositoryError> {
+        markdown_files_in(self.pages_dir())
+    }
 
-        fn sort_by_title_alphabetically(a: &Markdown, b: &Markdown) -> std::cmp::Ordering {
-            a.title.cmp(&b.title)
+    // Slugs cannot name other directories, but a symlinked file can still
+    // point outside of the content directory.
+    fn is_inside_content_dir(&self, path: &Path) -> bool {
+        let inside = match (path.canonicalize(), self.content_dir.canonicalize()) {
+            (Ok(path), Ok(content_dir)) => path.starts_with(content_dir),
+            _ => false,
+        };
+        if !inside {
+            eprintln!(
+                "Warning: {} is outside of the content directory and is not served",
+                path.display()
+            );
         }
-        markdowns.sort_by(|a, b| match (&a.publish_date, &b.publish_date) {
-            (Some(date_a), Some(date_b)) => sort_by_newest_first(date_a, date_b),
-            (Some(_), None) => std::cmp::Ordering::Less,
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => sort_by_title_alphabetically(a, b),
-        });
+        inside
+    }
 
+    fn all_posts_unsorted(&self) -> Result<Vec<Markdown>, RepositoryError> {
+        let markdowns = self
+            .post_files()?
+            .into_iter()
+            .map(SourceFile::into_markdown)
+            .collect();
         Ok(markdowns)
     }
+}
+
+impl BlogRepository for FileSystemBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        let mut markdowns = self.all_posts_unsorted()?;
+        sort_posts(&mut markdowns);
+        Ok(markdowns.into())
+    }
 
-    fn find_post_by_slug(&self, slug: &str) -> Result<Option<Markdown>, RepositoryError> {
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
         let markdowns = self.all_posts_unsorted()?;
         Ok(markdowns
             .into_iter()
             .find(|markdown| markdown.contains(slug.to_string())))
     }
 
-    fn get_page(&self, slug: &str) -> Result<Option<Markdown>, RepositoryError> {
-        let page_path = self.pages_dir().join(format!("{}.md", &slug));
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let page_path = self.pages_dir().join(format!("{}.md", slug));
 
-        if page_path.exists() {
+        if page_path.exists() && self.is_inside_content_dir(&page_path) {
             let content = read_to_string(page_path.clone())?;
             Ok(Some(Markdown {
                 title: None,
                 content,
                 slugs: vec![slug.to_string()],
                 publish_date: None,
+                modified: modified_time(&page_path),
+                updated: None,
+                front_matter: None,
             }))
         } else {
             Ok(None)
@@ -107,6 +163,99 @@ impl BlogRepository for FileSystemBlogRepository {
     }
 }
 
+// Newest first, undated posts last in alphabetical order.
+pub(crate) fn sort_posts(markdowns: &mut [Markdown]) {
+    fn sort_by_newest_first(
+        date_a: &chrono::NaiveDate,
+        date_b: &chrono::NaiveDate,
+    ) -> std::cmp::Ordering {
+        date_b.cmp(date_a)
+    }
+
+    fn sort_by_title_alphabetically(a: &Markdown, b: &Markdown) -> std::cmp::Ordering {
+        a.title.cmp(&b.title)
+    }
+    markdowns.sort_by(|a, b| match (&a.publish_date, &b.publish_date) {
+        (Some(date_a), Some(date_b)) => sort_by_newest_first(date_a, date_b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => sort_by_title_alphabetically(a, b),
+    });
+}
+
+fn markdown_files_in(dir: PathBuf) -> Result<Vec<SourceFile>, RepositoryError> {
+    markdown_entries_in(dir)?
+        .into_iter()
+        .map(|entry| {
+            Ok(SourceFile {
+                content: read_to_string(entry.path.clone())?,
+                path: entry.path,
+                modified: entry.modified,
+            })
+        })
+        .collect()
+}
+
+fn markdown_entries_in(dir: PathBuf) -> Result<Vec<SourceEntry>, RepositoryError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|_| RepositoryError::NotFound)? {
+        let entry = entry.map_err(|_| RepositoryError::UnexpectedError)?;
+        let path = entry.path();
+
+        if path.extension().is_none_or(|ext| ext != "md") {
+            continue;
+        }
+
+        let modified = modified_time(&path);
+        entries.push(SourceEntry { path, modified });
+    }
+    Ok(entries)
+}
+
+fn slug_of(path: &std::path::Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .expect("Expected file with extention")
+}
+
+fn modified_time(path: &std::path::Path) -> Option<chrono::DateTime<chrono::Utc>> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(chrono::DateTime::<chrono::Utc>::from)
+}
+
 fn read_to_string(page_path: PathBuf) -> Result<String, RepositoryError> {
     std::fs::read_to_string(page_path).map_err(|_| RepositoryError::UnexpectedError)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slug::slug;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn pages_symlinked_outside_of_content_dir_are_not_found() {
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("secret.md"), "Secret").unwrap();
+        let content = TempDir::new().unwrap();
+        fs::create_dir_all(content.path().join("pages")).unwrap();
+        fs::write(content.path().join("pages/about.md"), "About").unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.md"),
+            content.path().join("pages/secret.md"),
+        )
+        .unwrap();
+
+        let repo = FileSystemBlogRepository::new(content.path().to_path_buf());
+
+        assert!(repo.get_page(&slug("about")).unwrap().is_some());
+        assert!(repo.get_page(&slug("secret")).unwrap().is_none());
+    }
+}
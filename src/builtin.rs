@@ -0,0 +1,115 @@
+use axum::http::{StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use tera::Tera;
+
+use crate::theme::ThemeMetadata;
+
+// The default theme compiled into the binary. It is the last step of the
+// lookup chain, so a blog directory without templates or static files still
+// renders, and a theme only needs to contain the files it changes.
+const TEMPLATES: &[(&str, &str)] = &[
+    (
+        "base.html",
+        include_str!("../themes/default/templates/base.html"),
+    ),
+    (
+        "index.html",
+        include_str!("../themes/default/templates/index.html"),
+    ),
+    (
+        "post.html",
+        include_str!("../themes/default/templates/post.html"),
+    ),
+    (
+        "page.html",
+        include_str!("../themes/default/templates/page.html"),
+    ),
+    (
+        "history.html",
+        include_str!("../themes/default/templates/history.html"),
+    ),
+    (
+        "404.html",
+        include_str!("../themes/default/templates/404.html"),
+    ),
+    (
+        "500.html",
+        include_str!("../themes/default/templates/500.html"),
+    ),
+    (
+        "partials/header.html",
+        include_str!("../themes/default/templates/partials/header.html"),
+    ),
+    (
+        "partials/footer.html",
+        include_str!("../themes/default/templates/partials/footer.html"),
+    ),
+    (
+        "partials/meta.html",
+        include_str!("../themes/default/templates/partials/meta.html"),
+    ),
+];
+
+// Path below `static/`, content type and content.
+const STATIC_FILES: &[(&str, &str, &str)] = &[(
+    "css/main.css",
+    "text/css",
+    include_str!("../themes/default/static/css/main.css"),
+)];
+
+const METADATA: &str = include_str!("../themes/default/theme.yaml");
+
+// Also available as `theme/<name>`, like the templates of a theme on disk.
+pub(crate) fn templates() -> Tera {
+    let mut tera = Tera::default();
+    let sources = TEMPLATES.iter().flat_map(|(name, source)| {
+        [
+            (format!("theme/{name}"), *source),
+            (name.to_string(), *source),
+        ]
+    });
+    tera.add_raw_templates(sources)
+        .expect("Built-in templates should parse");
+    tera
+}
+
+pub(crate) fn metadata() -> ThemeMetadata {
+    ThemeMetadata::parse(METADATA).expect("Built-in theme.yaml should parse")
+}
+
+pub(crate) fn has_static_file(asset: &str) -> bool {
+    STATIC_FILES.iter().any(|(path, _, _)| *path == asset)
+}
+
+pub(crate) async fn static_file(uri: Uri) -> Response {
+    let asset = uri.path().trim_start_matches('/');
+    match STATIC_FILES.iter().find(|(path, _, _)| *path == asset) {
+        Some((_, content_type, content)) => {
+            ([(header::CONTENT_TYPE, *content_type)], *content).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_templates_cover_every_page() {
+        let tera = templates();
+        let names: Vec<&str> = tera.get_template_names().collect();
+
+        for name in [
+            "index.html",
+            "post.html",
+            "page.html",
+            "404.html",
+            "500.html",
+        ] {
+            assert!(names.contains(&name), "{name} missing");
+            assert!(names.contains(&format!("theme/{name}").as_str()));
+        }
+        assert_eq!(metadata().name, "Default");
+    }
+}
@@ -1,36 +1,320 @@
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use axum::http::StatusCode;
-use axum::response::Html;
 
 use async_trait::async_trait;
+use lru::LruCache;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::Renderer;
+use crate::api::{ContentJson, PostList, PostQuery};
+use crate::compression::Compressed;
+use crate::config::{CacheConfig, CompressionConfig};
+use crate::renderer::{RenderedHtml, Renderer};
+use crate::slug::Slug;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyClass {
+    Post,
+    Page,
+    Index,
+}
+
+// Rendered pages, or a page of the post list served as JSON.
+#[derive(Clone)]
+enum Cached {
+    Html(RenderedHtml),
+    Posts(Arc<PostList>),
+}
+
+struct CacheEntry {
+    value: Cached,
+    size: usize,
+    expires_at: Option<Instant>,
+    // Entries of an older generation were invalidated.
+    generation: u64,
+    // Ticks of the last read and of the last move to the recent end of the
+    // list; reads only bump the former so they can share the lock.
+    read_at: AtomicU64,
+    placed_at: u64,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: Instant, generation: u64) -> bool {
+        self.generation != generation || self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// Least recently placed first; see `HtmlCache::store` for how reads move
+// entries back.
+struct Entries {
+    by_key: LruCache<String, CacheEntry>,
+    bytes: usize,
+    // Generation of the last sweep for invalidated entries.
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    generation: AtomicU64,
+    clock: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+// Bounded by entry count and/or bytes; roughly the least recently read
+// entry is evicted first. Expired entries are dropped once they are read or
+// reach the end of the list, so no operation walks all entries except the
+// first insert after an invalidation. Hits only take the lock shared.
 #[derive(Clone)]
 struct HtmlCache {
-    cache: Arc<RwLock<HashMap<String, Html<String>>>>,
+    cache: Arc<RwLock<Entries>>,
+    counters: Arc<Counters>,
+    compression: CompressionConfig,
+    limits: CacheConfig,
 }
 
 impl HtmlCache {
-    fn new() -> Self {
+    fn new(compression: CompressionConfig, limits: CacheConfig) -> Self {
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache: Arc::new(RwLock::new(Entries {
+                by_key: LruCache::unbounded(),
+                bytes: 0,
+                generation: 0,
+            })),
+            counters: Arc::new(Counters::default()),
+            compression,
+            limits,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Option<RenderedHtml> {
+        match self.lookup(key).await {
+            Some(Cached::Html(rendered)) => Some(rendered),
+            _ => None,
+        }
+    }
+
+    async fn get_posts(&self, key: &str) -> Option<Arc<PostList>> {
+        match self.lookup(key).await {
+            Some(Cached::Posts(posts)) => Some(posts),
+            _ => None,
+        }
+    }
+
+    async fn lookup(&self, key: &str) -> Option<Cached> {
+        let expired = {
+            let entries = self.cache.read().await;
+            let (now, generation) = (Instant::now(), self.generation());
+            match entries.by_key.peek(key) {
+                Some(entry) if !entry.is_expired(now, generation) => {
+                    entry.read_at.store(self.tick(), Ordering::Relaxed);
+                    self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(entry.value.clone());
+                }
+                found => found.is_some(),
+            }
+        };
+        if expired {
+            let mut entries = self.cache.write().await;
+            let (now, generation) = (Instant::now(), self.generation());
+            if entries
+                .by_key
+                .peek(key)
+                .is_some_and(|entry| entry.is_expired(now, generation))
+            {
+                self.evict(&mut entries, key);
+            }
+        }
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    async fn contains(&self, key: &str) -> bool {
+        let entries = self.cache.read().await;
+        entries
+            .by_key
+            .peek(key)
+            .is_some_and(|entry| !entry.is_expired(Instant::now(), self.generation()))
+    }
+
+    // Stores the html together with its ETag and compressed variants and
+    // returns what was stored. `generation` is the one from before
+    // rendering, so html rendered across an invalidation is already stale.
+    async fn insert(
+        &self,
+        key: String,
+        class: KeyClass,
+        rendered: RenderedHtml,
+        generation: u64,
+    ) -> RenderedHtml {
+        let rendered = RenderedHtml {
+            etag: Some(etag_for(&rendered.html.0)),
+            compressed: Some(Compressed::new(&rendered.html.0, &self.compression)),
+            ..rendered
+        };
+        let size = size_of_entry(&key, &rendered);
+        self.store(key, class, Cached::Html(rendered.clone()), size, generation)
+            .await;
+        rendered
+    }
+
+    async fn insert_posts(&self, key: String, posts: Arc<PostList>, generation: u64) {
+        let size = key.len()
+            + posts
+                .posts
+                .iter()
+                .map(|post| post.markdown.len() + post.html.len())
+                .sum::<usize>();
+        self.store(key, KeyClass::Index, Cached::Posts(posts), size, generation)
+            .await;
+    }
+
+    async fn store(
+        &self,
+        key: String,
+        class: KeyClass,
+        value: Cached,
+        size: usize,
+        generation: u64,
+    ) {
+        let mut entries = self.cache.write().await;
+        self.drop_invalidated(&mut entries);
+        if generation != entries.generation {
+            return;
+        }
+
+        let placed_at = self.tick();
+        let entry = CacheEntry {
+            value,
+            size,
+            expires_at: self.ttl_for(class).map(|ttl| Instant::now() + ttl),
+            generation,
+            read_at: AtomicU64::new(placed_at),
+            placed_at,
+        };
+        entries.bytes += entry.size;
+        if let Some(previous) = entries.by_key.put(key, entry) {
+            entries.bytes -= previous.size;
+        }
+
+        // An entry read since it was placed gets a second chance at the
+        // recent end instead of being evicted. Each entry moves at most once
+        // per read, and no reads happen while the lock is held exclusively.
+        let now = Instant::now();
+        while self.is_over_limits(&entries) {
+            let Some((key, mut entry)) = entries.by_key.pop_lru() else {
+                break;
+            };
+            let read_at = *entry.read_at.get_mut();
+            if read_at > entry.placed_at && !entry.is_expired(now, generation) {
+                entry.placed_at = read_at;
+                entries.by_key.put(key, entry);
+                continue;
+            }
+            entries.bytes -= entry.size;
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn drop_invalidated(&self, entries: &mut Entries) {
+        let generation = self.generation();
+        if entries.generation == generation {
+            return;
         }
+        let Entries { by_key, bytes, .. } = entries;
+        by_key.retain(|_, entry| {
+            let current = entry.generation == generation;
+            if !current {
+                *bytes -= entry.size;
+                self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            current
+        });
+        entries.generation = generation;
     }
 
-    async fn get(&self, key: &str) -> Option<Html<String>> {
-        self.cache.read().await.get(key).cloned()
+    fn is_over_limits(&self, entries: &Entries) -> bool {
+        let too_many = self
+            .limits
+            .max_entries
+            .is_some_and(|max| entries.by_key.len() > max);
+        let too_large = self.limits.max_bytes.is_some_and(|max| entries.bytes > max);
+        too_many || too_large
     }
 
-    async fn insert(&self, key: String, html: Html<String>) {
-        self.cache.write().await.insert(key, html);
+    fn evict(&self, entries: &mut Entries, key: &str) {
+        if let Some(entry) = entries.by_key.pop(key) {
+            entries.bytes -= entry.size;
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn ttl_for(&self, class: KeyClass) -> Option<Duration> {
+        let ttl = &self.limits.ttl_seconds;
+        match class {
+            KeyClass::Post => ttl.post,
+            KeyClass::Page => ttl.page,
+            KeyClass::Index => ttl.index,
+        }
+        .map(Duration::from_secs)
+    }
+
+    fn generation(&self) -> u64 {
+        self.counters.generation.load(Ordering::Acquire)
+    }
+
+    fn tick(&self) -> u64 {
+        self.counters.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // Turns every entry into a miss without waiting for the lock; they are
+    // removed on the next insert.
+    fn invalidate(&self) {
+        self.counters.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    async fn stats(&self) -> CacheStats {
+        let entries = self.cache.read().await;
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            entries: entries.by_key.len(),
+            bytes: entries.bytes,
+        }
     }
 }
 
+fn size_of_entry(key: &str, rendered: &RenderedHtml) -> usize {
+    let compressed = rendered
+        .compressed
+        .as_ref()
+        .map_or(0, |c| c.gzip.len() + c.brotli.len());
+    key.len() + rendered.html.0.len() + compressed
+}
+
+// Strong validator: quoted hex SHA-256 of the response body.
+fn etag_for(html: &str) -> String {
+    let digest = Sha256::digest(html.as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\"", hex)
+}
+
 #[derive(Clone)]
 pub struct CachedRenderer {
     renderer: Arc<dyn Renderer + Send + Sync>,
@@ -38,60 +322,433 @@ pub struct CachedRenderer {
 }
 
 impl CachedRenderer {
-    pub fn new(renderer: Arc<dyn Renderer + Send + Sync>) -> Self {
+    pub fn new(
+        renderer: Arc<dyn Renderer + Send + Sync>,
+        compression: CompressionConfig,
+        limits: CacheConfig,
+    ) -> Self {
         Self {
             renderer,
-            cache: HtmlCache::new(),
+            cache: HtmlCache::new(compression, limits),
         }
     }
 
-    pub async fn preload_posts(&self, slugs: Vec<String>) -> Result<(), StatusCode> {
+    pub async fn preload_posts(&self, slugs: Vec<Slug>) -> Result<(), StatusCode> {
         // Pre-render all posts and cache them
         for slug in slugs {
             let cache_key = format!("post:{}", slug);
 
             // Only preload if not already cached
-            if self.cache.get(&cache_key).await.is_none() {
+            if !self.cache.contains(&cache_key).await {
+                let generation = self.cache.generation();
                 let rendered_html = self.renderer.post_for(slug).await?;
-                self.cache.insert(cache_key, rendered_html).await;
+                self.cache
+                    .insert(cache_key, KeyClass::Post, rendered_html, generation)
+                    .await;
             }
         }
         Ok(())
     }
+
+    pub async fn stats(&self) -> CacheStats {
+        self.cache.stats().await
+    }
+
+    // For callers that cannot wait, like repositories changed from sync code.
+    pub fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    async fn cached(
+        &self,
+        key: String,
+        class: KeyClass,
+        render: impl Future<Output = Result<RenderedHtml, StatusCode>>,
+    ) -> Result<RenderedHtml, StatusCode> {
+        if let Some(cached_html) = self.cache.get(&key).await {
+            return Ok(cached_html);
+        }
+
+        let generation = self.cache.generation();
+        let rendered_html = render.await?;
+        Ok(self
+            .cache
+            .insert(key, class, rendered_html, generation)
+            .await)
+    }
 }
 
 #[async_trait]
 impl Renderer for CachedRenderer {
-    async fn post_for(&self, slug: String) -> Result<Html<String>, StatusCode> {
+    async fn post_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
         let cache_key = format!("post:{}", slug);
-        if let Some(cached_html) = self.cache.get(&cache_key).await {
-            return Ok(cached_html);
+        self.cached(cache_key, KeyClass::Post, self.renderer.post_for(slug))
+            .await
+    }
+
+    async fn page_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
+        let cache_key = format!("page:{}", slug);
+        self.cached(cache_key, KeyClass::Page, self.renderer.page_for(slug))
+            .await
+    }
+
+    async fn posts(&self) -> Result<RenderedHtml, StatusCode> {
+        let cache_key = "posts_index".to_string();
+        self.cached(cache_key, KeyClass::Index, self.renderer.posts())
+            .await
+    }
+
+    // Diffs are requested rarely and with arbitrary revisions, so only the
+    // revision list is cached.
+    async fn history_for(
+        &self,
+        slug: Slug,
+        diff: Option<(String, String)>,
+    ) -> Result<RenderedHtml, StatusCode> {
+        if diff.is_some() {
+            return self.renderer.history_for(slug, diff).await;
         }
 
-        let rendered_html = self.renderer.post_for(slug).await?;
-        self.cache.insert(cache_key, rendered_html.clone()).await;
-        Ok(rendered_html)
+        let cache_key = format!("history:{}", slug);
+        self.cached(
+            cache_key,
+            KeyClass::Post,
+            self.renderer.history_for(slug, None),
+        )
+        .await
     }
 
-    async fn page_for(&self, slug: String) -> Result<Html<String>, StatusCode> {
-        let cache_key = format!("page:{}", slug);
-        if let Some(cached_html) = self.cache.get(&cache_key).await {
-            return Ok(cached_html);
+    async fn post_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        self.renderer.post_json(slug).await
+    }
+
+    async fn page_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        self.renderer.page_json(slug).await
+    }
+
+    // Every post of a page is rendered, so the unfiltered pages are cached.
+    // Filters and page numbers past the end are arbitrary and would let
+    // requests fill the cache.
+    async fn posts_json(&self, query: PostQuery) -> Result<Arc<PostList>, StatusCode> {
+        let Some(cache_key) = query.cache_key() else {
+            return self.renderer.posts_json(query).await;
+        };
+        if let Some(posts) = self.cache.get_posts(&cache_key).await {
+            return Ok(posts);
         }
 
-        let rendered_html = self.renderer.page_for(slug).await?;
-        self.cache.insert(cache_key, rendered_html.clone()).await;
-        Ok(rendered_html)
+        let generation = self.cache.generation();
+        let posts = self.renderer.posts_json(query).await?;
+        if posts.page <= posts.total_pages.max(1) {
+            self.cache
+                .insert_posts(cache_key, posts.clone(), generation)
+                .await;
+        }
+        Ok(posts)
     }
 
-    async fn posts(&self) -> Result<Html<String>, StatusCode> {
-        let cache_key = "posts_index".to_string();
-        if let Some(cached_html) = self.cache.get(&cache_key).await {
-            return Ok(cached_html);
+    // Error pages are rendered for arbitrary paths, caching them would let
+    // requests fill the cache.
+    async fn error_for(
+        &self,
+        status: StatusCode,
+        path: String,
+    ) -> Result<RenderedHtml, StatusCode> {
+        self.renderer.error_for(status, path).await
+    }
+
+    fn highlight_stylesheet(&self) -> Option<String> {
+        self.renderer.highlight_stylesheet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TtlConfig;
+
+    fn cache(limits: CacheConfig) -> HtmlCache {
+        HtmlCache::new(CompressionConfig::default(), limits)
+    }
+
+    fn html(body: &str) -> RenderedHtml {
+        RenderedHtml::new(body.to_string(), None)
+    }
+
+    #[tokio::test]
+    async fn counts_hits_and_misses() {
+        let cache = cache(CacheConfig::default());
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+
+        assert!(cache.get("post:a").await.is_some());
+        assert!(cache.get("post:b").await.is_none());
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_read_entry_when_over_max_entries() {
+        let cache = cache(CacheConfig {
+            max_entries: Some(2),
+            ..CacheConfig::default()
+        });
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+        cache
+            .insert(
+                "post:b".into(),
+                KeyClass::Post,
+                html("b"),
+                cache.generation(),
+            )
+            .await;
+        cache.get("post:a").await;
+
+        cache
+            .insert(
+                "post:c".into(),
+                KeyClass::Post,
+                html("c"),
+                cache.generation(),
+            )
+            .await;
+
+        assert!(cache.contains("post:a").await);
+        assert!(!cache.contains("post:b").await);
+        assert!(cache.contains("post:c").await);
+        assert_eq!(cache.stats().await.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn hits_share_the_lock() {
+        let cache = cache(CacheConfig::default());
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+
+        let _reader = cache.cache.read().await;
+        assert!(cache.get("post:a").await.is_some());
+        assert!(cache.contains("post:a").await);
+    }
+
+    #[tokio::test]
+    async fn evicts_until_under_max_bytes() {
+        let cache = cache(CacheConfig::default());
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+        let one_entry = cache.stats().await.bytes;
+
+        let cache = self::cache(CacheConfig {
+            max_bytes: Some(one_entry * 2),
+            ..CacheConfig::default()
+        });
+        for key in ["post:a", "post:b", "post:c"] {
+            cache
+                .insert(key.into(), KeyClass::Post, html("a"), cache.generation())
+                .await;
         }
 
-        let rendered_html = self.renderer.posts().await?;
-        self.cache.insert(cache_key, rendered_html.clone()).await;
-        Ok(rendered_html)
+        let stats = cache.stats().await;
+        assert_eq!(stats.entries, 2);
+        assert!(stats.bytes <= one_entry * 2);
+    }
+
+    #[tokio::test]
+    async fn replacing_an_entry_keeps_byte_count_accurate() {
+        let cache = cache(CacheConfig::default());
+        cache
+            .insert(
+                "page:a".into(),
+                KeyClass::Page,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+        let bytes = cache.stats().await.bytes;
+
+        cache
+            .insert(
+                "page:a".into(),
+                KeyClass::Page,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+
+        assert_eq!(cache.stats().await.bytes, bytes);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_misses_per_key_class_and_dropped_when_read() {
+        let cache = cache(CacheConfig {
+            ttl_seconds: TtlConfig {
+                index: Some(0),
+                ..TtlConfig::default()
+            },
+            ..CacheConfig::default()
+        });
+        cache
+            .insert(
+                "posts_index".into(),
+                KeyClass::Index,
+                html("index"),
+                cache.generation(),
+            )
+            .await;
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+
+        assert_eq!(cache.stats().await.entries, 2);
+        assert!(cache.get("posts_index").await.is_none());
+        assert!(cache.get("post:a").await.is_some());
+        assert_eq!(cache.stats().await.entries, 1);
+    }
+
+    // Lists three pages of posts and counts how often it was asked.
+    #[derive(Default)]
+    struct CountingRenderer {
+        post_lists: AtomicU64,
+    }
+
+    #[async_trait]
+    impl Renderer for CountingRenderer {
+        async fn post_for(&self, _: Slug) -> Result<RenderedHtml, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn page_for(&self, _: Slug) -> Result<RenderedHtml, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn posts(&self) -> Result<RenderedHtml, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn history_for(
+            &self,
+            _: Slug,
+            _: Option<(String, String)>,
+        ) -> Result<RenderedHtml, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn post_json(&self, _: Slug) -> Result<ContentJson, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn page_json(&self, _: Slug) -> Result<ContentJson, StatusCode> {
+            Err(StatusCode::NOT_FOUND)
+        }
+
+        async fn posts_json(&self, query: PostQuery) -> Result<Arc<PostList>, StatusCode> {
+            self.post_lists.fetch_add(1, Ordering::Relaxed);
+            Ok(Arc::new(PostList {
+                posts: Vec::new(),
+                page: query.page.unwrap_or(1),
+                per_page: 20,
+                total: 60,
+                total_pages: 3,
+            }))
+        }
+
+        fn highlight_stylesheet(&self) -> Option<String> {
+            None
+        }
+
+        async fn error_for(
+            &self,
+            status: StatusCode,
+            _: String,
+        ) -> Result<RenderedHtml, StatusCode> {
+            Err(status)
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_unfiltered_post_list_pages_that_exist() {
+        let renderer = Arc::new(CountingRenderer::default());
+        let cached = CachedRenderer::new(
+            renderer.clone(),
+            CompressionConfig::default(),
+            CacheConfig::default(),
+        );
+        let page = |page: usize| PostQuery {
+            page: Some(page),
+            ..PostQuery::default()
+        };
+        let filtered = || PostQuery {
+            q: Some("rust".to_string()),
+            ..PostQuery::default()
+        };
+
+        for query in [page(2), page(2), page(4), page(4), filtered(), filtered()] {
+            cached.posts_json(query).await.unwrap();
+        }
+
+        assert_eq!(renderer.post_lists.load(Ordering::Relaxed), 5);
+        assert_eq!(cached.stats().await.entries, 1);
+    }
+
+    #[tokio::test]
+    async fn invalidated_entries_are_misses_including_ones_rendered_before() {
+        let cache = cache(CacheConfig::default());
+        cache
+            .insert(
+                "post:a".into(),
+                KeyClass::Post,
+                html("a"),
+                cache.generation(),
+            )
+            .await;
+        let before_change = cache.generation();
+
+        cache.invalidate();
+        cache
+            .insert("post:b".into(), KeyClass::Post, html("b"), before_change)
+            .await;
+        cache
+            .insert(
+                "post:c".into(),
+                KeyClass::Post,
+                html("c"),
+                cache.generation(),
+            )
+            .await;
+
+        assert!(cache.get("post:a").await.is_none());
+        assert!(cache.get("post:b").await.is_none());
+        assert!(cache.get("post:c").await.is_some());
+        assert_eq!(cache.stats().await.entries, 1);
     }
 }
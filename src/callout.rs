@@ -0,0 +1,375 @@
+use pulldown_cmark::{Event, Tag, html};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Kind> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "note" => Kind::Note,
+            "tip" => Kind::Tip,
+            "important" => Kind::Important,
+            "warning" => Kind::Warning,
+            "caution" => Kind::Caution,
+            _ => return None,
+        })
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Kind::Note => "note",
+            Kind::Tip => "tip",
+            Kind::Important => "important",
+            Kind::Warning => "warning",
+            Kind::Caution => "caution",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Kind::Note => "Note",
+            Kind::Tip => "Tip",
+            Kind::Important => "Important",
+            Kind::Warning => "Warning",
+            Kind::Caution => "Caution",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Kind::Note => "\u{2139}",
+            Kind::Tip => "\u{2605}",
+            Kind::Important => "\u{2757}",
+            Kind::Warning => "\u{26A0}",
+            Kind::Caution => "\u{26D4}",
+        }
+    }
+}
+
+// The events of one line of a paragraph, with the break ending it.
+type Line<'a> = Vec<Event<'a>>;
+
+enum Fence {
+    // `None` for types that are not callouts; they become blockquotes.
+    Open(Option<Kind>, Option<String>),
+    Close,
+}
+
+// Turns blockquotes starting with `[!NOTE]` and containers between
+// `:::warning` and `:::` lines into asides. Text after the type is used as
+// title. Blockquotes of other types are left alone, containers of other
+// types become blockquotes.
+pub(crate) fn callouts(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut output = Vec::with_capacity(events.len());
+    // Whether each open blockquote was turned into an aside.
+    let mut quotes = Vec::new();
+    // Containers belong to the blockquote or list item they start in and
+    // are closed with it; the ones of enclosing blocks wait in `outer`.
+    let mut containers = Vec::new();
+    let mut outer = Vec::new();
+    let mut events = events.into_iter().peekable();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::BlockQuote) => {
+                outer.push(std::mem::take(&mut containers));
+                if events.next_if_eq(&Event::Start(Tag::Paragraph)).is_none() {
+                    quotes.push(false);
+                    output.push(Event::Start(Tag::BlockQuote));
+                    continue;
+                }
+                let mut lines = paragraph_lines(&mut events);
+                match lines.first().and_then(|line| quote_marker(line)) {
+                    Some((kind, title)) => {
+                        quotes.push(true);
+                        output.push(Event::Html(open(kind, title).into()));
+                        lines.remove(0);
+                    }
+                    None => {
+                        quotes.push(false);
+                        output.push(Event::Start(Tag::BlockQuote));
+                    }
+                }
+                push_paragraphs(&mut output, lines, &mut containers, false);
+            }
+            Event::End(Tag::BlockQuote) => {
+                close_all(&mut output, &mut containers);
+                containers = outer.pop().unwrap_or_default();
+                match quotes.pop() {
+                    Some(true) => output.push(Event::Html("</aside>\n".into())),
+                    _ => output.push(Event::End(Tag::BlockQuote)),
+                }
+            }
+            // Text of tight list items is not wrapped in a paragraph.
+            Event::Start(Tag::Item) => {
+                output.push(event);
+                outer.push(std::mem::take(&mut containers));
+                let lines = lines_of(std::iter::from_fn(|| events.next_if(is_inline)));
+                push_paragraphs(&mut output, lines, &mut containers, true);
+            }
+            Event::End(Tag::Item) => {
+                close_all(&mut output, &mut containers);
+                containers = outer.pop().unwrap_or_default();
+                output.push(event);
+            }
+            Event::Start(Tag::Paragraph) => {
+                let lines = paragraph_lines(&mut events);
+                push_paragraphs(&mut output, lines, &mut containers, false);
+            }
+            event => output.push(event),
+        }
+    }
+    close_all(&mut output, &mut containers);
+    output
+}
+
+fn close_all(output: &mut Vec<Event<'_>>, containers: &mut Vec<Option<Kind>>) {
+    while let Some(kind) = containers.pop() {
+        output.push(close(kind));
+    }
+}
+
+fn is_inline(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) | Event::End(tag) => matches!(
+            tag,
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+        ),
+        Event::Rule => false,
+        _ => true,
+    }
+}
+
+// The rest of a paragraph, including its end, split into lines.
+fn paragraph_lines<'a>(events: &mut impl Iterator<Item = Event<'a>>) -> Vec<Line<'a>> {
+    lines_of(events.take_while(|event| *event != Event::End(Tag::Paragraph)))
+}
+
+fn lines_of<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Line<'a>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for event in events {
+        match event {
+            Event::SoftBreak | Event::HardBreak => {
+                line.push(event);
+                lines.push(std::mem::take(&mut line));
+            }
+            event => line.push(event),
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+// Lines are written as paragraphs, cut where a container starts or ends.
+// Tight lines are written without paragraph tags.
+fn push_paragraphs<'a>(
+    output: &mut Vec<Event<'a>>,
+    lines: Vec<Line<'a>>,
+    containers: &mut Vec<Option<Kind>>,
+    tight: bool,
+) {
+    let mut paragraph: Vec<Line> = Vec::new();
+    for line in lines {
+        match fence_marker(&line) {
+            Some(Fence::Open(kind, title)) => {
+                push_paragraph(output, &mut paragraph, tight);
+                match kind {
+                    Some(kind) => output.push(Event::Html(open(kind, title).into())),
+                    None => {
+                        output.push(Event::Start(Tag::BlockQuote));
+                        if let Some(title) = title {
+                            output.push(Event::Html(format!("<p>{}</p>\n", title).into()));
+                        }
+                    }
+                }
+                containers.push(kind);
+            }
+            Some(Fence::Close) if !containers.is_empty() => {
+                push_paragraph(output, &mut paragraph, tight);
+                if let Some(kind) = containers.pop() {
+                    output.push(close(kind));
+                }
+            }
+            _ => paragraph.push(line),
+        }
+    }
+    push_paragraph(output, &mut paragraph, tight);
+}
+
+fn push_paragraph<'a>(output: &mut Vec<Event<'a>>, lines: &mut Vec<Line<'a>>, tight: bool) {
+    let mut events: Vec<Event> = lines.drain(..).flatten().collect();
+    if matches!(events.last(), Some(Event::SoftBreak | Event::HardBreak)) {
+        events.pop();
+    }
+    if events.is_empty() {
+        return;
+    }
+    if tight {
+        output.extend(events);
+        return;
+    }
+    output.push(Event::Start(Tag::Paragraph));
+    output.extend(events);
+    output.push(Event::End(Tag::Paragraph));
+}
+
+// Markdown splits text at brackets, so the leading text events are joined
+// before looking for a marker. Returns the text and the events after it.
+fn leading_text<'l, 'a>(line: &'l [Event<'a>]) -> (String, &'l [Event<'a>]) {
+    let count = line
+        .iter()
+        .take_while(|event| matches!(event, Event::Text(_)))
+        .count();
+    let text = line[..count]
+        .iter()
+        .filter_map(|event| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect();
+    (text, &line[count..])
+}
+
+// `[!TYPE]` with an optional title after it.
+fn quote_marker(line: &[Event]) -> Option<(Kind, Option<String>)> {
+    let (text, rest) = leading_text(line);
+    let marker = text.trim_start().strip_prefix("[!")?;
+    let (name, title) = marker.split_once(']')?;
+    let kind = Kind::parse(name)?;
+    Some((kind, title_html(title, rest)))
+}
+
+// `:::type` with an optional title, or a `:::` line.
+fn fence_marker(line: &[Event]) -> Option<Fence> {
+    let (text, rest) = leading_text(line);
+    let marker = text.trim().strip_prefix(":::")?.trim_start();
+    let rest = match rest {
+        [rest @ .., Event::SoftBreak | Event::HardBreak] => rest,
+        rest => rest,
+    };
+    if marker.is_empty() {
+        return rest.is_empty().then_some(Fence::Close);
+    }
+    let name_length = marker
+        .find(|c: char| !c.is_alphanumeric() && c != '-')
+        .unwrap_or(marker.len());
+    let (name, title) = marker.split_at(name_length);
+    if name.is_empty() {
+        return None;
+    }
+    Some(Fence::Open(Kind::parse(name), title_html(title, rest)))
+}
+
+fn title_html(text: &str, rest: &[Event]) -> Option<String> {
+    let mut events = vec![Event::Text(text.to_string().into())];
+    events.extend(
+        rest.iter()
+            .filter(|event| !matches!(event, Event::SoftBreak | Event::HardBreak))
+            .cloned(),
+    );
+    let mut title = String::new();
+    html::push_html(&mut title, events.into_iter());
+    let title = title.trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+fn open(kind: Kind, title: Option<String>) -> String {
+    format!(
+        "<aside class=\"callout callout-{}\" role=\"note\">\n<p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">{}</span> {}</p>\n",
+        kind.class(),
+        kind.icon(),
+        title.as_deref().unwrap_or(kind.label())
+    )
+}
+
+fn close(kind: Option<Kind>) -> Event<'static> {
+    match kind {
+        Some(_) => Event::Html("</aside>\n".into()),
+        None => Event::End(Tag::BlockQuote),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    fn render(markdown: &str) -> String {
+        let mut html = String::new();
+        html::push_html(
+            &mut html,
+            callouts(Parser::new(markdown).collect()).into_iter(),
+        );
+        html
+    }
+
+    #[test]
+    fn blockquotes_with_a_type_become_asides() {
+        assert_eq!(
+            render("> [!NOTE]\n> Read *this*.\n\n> [!warning] Mind the gap\n>\n> Really."),
+            "<aside class=\"callout callout-note\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2139}</span> Note</p>\n\
+             <p>Read <em>this</em>.</p>\n</aside>\n\
+             <aside class=\"callout callout-warning\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{26A0}</span> Mind the gap</p>\n\
+             <p>Really.</p>\n</aside>\n"
+        );
+        assert_eq!(
+            render("> [!UNKNOWN]\n> Text"),
+            "<blockquote>\n<p>[!UNKNOWN]\nText</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn fenced_containers_become_asides() {
+        assert_eq!(
+            render(":::tip Shortcut\nPress `q`.\n:::\n\n:::aside\nOther\n:::\nAfter"),
+            "<aside class=\"callout callout-tip\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2605}</span> Shortcut</p>\n\
+             <p>Press <code>q</code>.</p>\n</aside>\n\
+             <blockquote>\n<p>Other</p>\n</blockquote>\n<p>After</p>\n"
+        );
+        assert_eq!(
+            render(":::caution\n\n- one\n- two\n\n:::"),
+            "<aside class=\"callout callout-caution\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{26D4}</span> Caution</p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n</aside>\n"
+        );
+    }
+
+    #[test]
+    fn unclosed_containers_end_with_their_blockquote() {
+        assert_eq!(
+            render("> :::note\n> inside\n\nafter"),
+            "<blockquote>\n<aside class=\"callout callout-note\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2139}</span> Note</p>\n\
+             <p>inside</p>\n</aside>\n</blockquote>\n<p>after</p>\n"
+        );
+    }
+
+    #[test]
+    fn containers_in_list_items_end_with_the_item() {
+        assert_eq!(
+            render("- item\n  :::tip\n  inside\n  :::\n- :::note\n  unclosed\n- next"),
+            "<ul>\n<li>item<aside class=\"callout callout-tip\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2605}</span> Tip</p>\n\
+             inside</aside>\n</li>\n\
+             <li><aside class=\"callout callout-note\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2139}</span> Note</p>\n\
+             unclosed</aside>\n</li>\n<li>next</li>\n</ul>\n"
+        );
+        assert_eq!(
+            render("- one\n\n  :::note\n  inside\n\n- two"),
+            "<ul>\n<li>\n<p>one</p>\n<aside class=\"callout callout-note\" role=\"note\">\n\
+             <p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">\u{2139}</span> Note</p>\n\
+             <p>inside</p>\n</aside>\n</li>\n<li>\n<p>two</p>\n</li>\n</ul>\n"
+        );
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::Serialize;
+
+use crate::blog_repository::{FileSystemBlogRepository, SourceFile};
+use crate::composite_repository::CompositeBlogRepository;
+use crate::config::{Backend, BlogConfig};
+use crate::directories::{BlogDir, ContentDir};
+use crate::model::{Markdown, parse_date_for_sorting};
+use crate::renderer::BlogPostHandler;
+use crate::slug::Slug;
+use crate::template_validation::validate_templates;
+use crate::theme::{Theme, ThemeDirs};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub file: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CheckReport {
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors() > 0
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Check report should serialize to JSON")
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    fn error(&mut self, file: &str, message: String) {
+        self.push(Severity::Error, file, message);
+    }
+
+    fn warning(&mut self, file: &str, message: String) {
+        self.push(Severity::Warning, file, message);
+    }
+
+    fn push(&mut self, severity: Severity, file: &str, message: String) {
+        self.issues.push(Issue {
+            severity,
+            file: file.to_string(),
+            message,
+        });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            let severity = match issue.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(f, "{}: {}: {}", severity, issue.file, issue.message)?;
+        }
+        write!(
+            f,
+            "{} error(s), {} warning(s)",
+            self.errors(),
+            self.warnings()
+        )
+    }
+}
+
+// Validates all posts and pages in the content directory without starting a server.
+pub async fn check_blog<P: Into<PathBuf>>(content_dir: P, blog_dir: P) -> CheckReport {
+    let content_dir = content_dir.into();
+    let config = BlogConfig::from_file_or_default(ContentDir(content_dir.clone()).config_file());
+    check_blog_with_config(content_dir, blog_dir.into(), config).await
+}
+
+// Like `check_blog` with the config the server runs with, so every content
+// directory it serves is checked. Content of other backends is not.
+pub async fn check_blog_with_config<P: Into<PathBuf>>(
+    content_dir: P,
+    blog_dir: P,
+    config: BlogConfig,
+) -> CheckReport {
+    let content_dir = ContentDir(content_dir.into());
+    let blog_dir = BlogDir(blog_dir.into());
+    let mut report = CheckReport::default();
+
+    let dirs: Vec<PathBuf> = match config.storage.backend {
+        Backend::Files => std::iter::once(content_dir.dir())
+            .chain(
+                config
+                    .storage
+                    .content_dirs
+                    .iter()
+                    .map(|dir| content_dir.dir().join(dir)),
+            )
+            .collect(),
+        backend => {
+            report.warning(
+                "storage",
+                format!("content of the {backend} backend is not checked"),
+            );
+            Vec::new()
+        }
+    };
+
+    // Posts and pages per directory, in priority order like the server
+    // merges them.
+    let mut sources = Vec::new();
+    for dir in &dirs {
+        let repo = FileSystemBlogRepository::new(dir.clone());
+        match (repo.post_files(), repo.page_files()) {
+            (Ok(posts), Ok(pages)) => sources.push((posts, pages)),
+            _ => report.error(
+                &dir.display().to_string(),
+                "content directory could not be read".to_string(),
+            ),
+        }
+    }
+    let posts: Vec<&SourceFile> = sources.iter().flat_map(|(posts, _)| posts).collect();
+    let pages: Vec<&SourceFile> = sources.iter().flat_map(|(_, pages)| pages).collect();
+
+    // Markdown without front matter, for the link checks.
+    let mut bodies: Vec<(String, String)> = Vec::new();
+    let mut post_slugs: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (posts, _) in &sources {
+        let mut dir_slugs: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for post in posts {
+            let file = relative_name(&content_dir, post);
+            let markdown = check_front_matter(&mut report, &file, post);
+            bodies.push((file.clone(), markdown.content));
+            for slug in markdown.slugs {
+                dir_slugs.entry(slug).or_default().insert(file.clone());
+            }
+        }
+        for (slug, files) in dir_slugs {
+            let files: Vec<String> = files.into_iter().collect();
+            if files.len() > 1 {
+                report.error(
+                    &files[0],
+                    format!(
+                        "slug '{}' is used by multiple posts: {}",
+                        slug,
+                        files.join(", ")
+                    ),
+                );
+            }
+            match post_slugs.get(&slug).and_then(|owners| owners.first()) {
+                Some(owner) => {
+                    for file in &files {
+                        report.warning(file, format!("slug '{}' is hidden by {}", slug, owner));
+                    }
+                }
+                None => {
+                    post_slugs.insert(slug, files.into_iter().collect());
+                }
+            }
+        }
+    }
+
+    if let Err(e) = Theme::load(&blog_dir, &config) {
+        report.error("theme", e.to_string());
+    }
+    let theme_dirs = ThemeDirs::new(&blog_dir, &config);
+    for issue in validate_templates(&blog_dir, &config) {
+        let file = match &issue.template {
+            Some(template) => format!("templates/{template}"),
+            None => "templates".to_string(),
+        };
+        report.error(&file, issue.message);
+    }
+
+    let page_slugs: BTreeSet<String> = pages.iter().map(|page| page.slug()).collect();
+    bodies.extend(pages.iter().map(|page| {
+        (
+            relative_name(&content_dir, page),
+            Markdown::parse_front_matter(&page.content).content,
+        )
+    }));
+    for (file, body) in &bodies {
+        check_links(
+            &mut report,
+            file,
+            body,
+            &post_slugs,
+            &page_slugs,
+            &theme_dirs,
+        );
+    }
+
+    let repo = dirs
+        .iter()
+        .fold(CompositeBlogRepository::new(), |repo, dir| {
+            repo.with_source(FileSystemBlogRepository::new(dir.clone()))
+        });
+    let handler = BlogPostHandler::new(config, repo, &blog_dir);
+    if let Err(status) = handler.render_posts().await {
+        report.error("index", format!("index failed to render ({})", status));
+    }
+    for post in &posts {
+        let Some(slug) = requestable_slug(&mut report, &content_dir, post) else {
+            continue;
+        };
+        if let Err(status) = handler.render_post(slug).await {
+            report.error(
+                &relative_name(&content_dir, post),
+                format!("post failed to render ({})", status),
+            );
+        }
+    }
+    for page in &pages {
+        let Some(slug) = requestable_slug(&mut report, &content_dir, page) else {
+            continue;
+        };
+        if let Err(status) = handler.render_page(slug).await {
+            report.error(
+                &relative_name(&content_dir, page),
+                format!("page failed to render ({})", status),
+            );
+        }
+    }
+    for file in posts.iter().chain(pages.iter()) {
+        for language in handler.unknown_languages(&file.content) {
+            report.warning(
+                &relative_name(&content_dir, file),
+                format!(
+                    "code block language '{}' is not known, it is not highlighted",
+                    language
+                ),
+            );
+        }
+    }
+
+    report
+}
+
+fn check_front_matter(report: &mut CheckReport, file: &str, source: &SourceFile) -> Markdown {
+    let parsed = Markdown::parse_front_matter(&source.content);
+    if let Some(error) = &parsed.front_matter_error {
+        report.error(file, format!("front matter could not be parsed: {}", error));
+    }
+    if let Some(date) = parsed
+        .front_matter
+        .as_ref()
+        .and_then(|f| f.publish_date.as_ref())
+        && parse_date_for_sorting(date).is_none()
+    {
+        report.error(file, format!("date '{}' is not a recognised format", date));
+    }
+
+    let mut markdown = Markdown::from_parsed(parsed);
+    if markdown.title.is_none() {
+        report.warning(file, "missing title (rendered as \"Untitled\")".to_string());
+    }
+    markdown.slugs.push(source.slug());
+    markdown
+}
+
+fn check_links(
+    report: &mut CheckReport,
+    file: &str,
+    body: &str,
+    post_slugs: &BTreeMap<String, BTreeSet<String>>,
+    page_slugs: &BTreeSet<String>,
+    theme_dirs: &ThemeDirs,
+) {
+    for event in Parser::new(body) {
+        match event {
+            Event::Start(Tag::Link(_, dest, _)) => {
+                let target = strip_fragment(&dest);
+                if let Some(asset) = target.strip_prefix("/static/") {
+                    if !theme_dirs.has_static_file(asset) {
+                        report.error(file, format!("link to missing static file '{}'", dest));
+                    }
+                } else if let Some(page) = target.strip_prefix("/p/") {
+                    if !page_slugs.contains(page) {
+                        report.error(file, format!("link to non-existent page '{}'", dest));
+                    }
+                } else if let Some(post) = target.strip_prefix('/') {
+                    let is_single_segment = !post.is_empty() && !post.contains('/');
+                    if is_single_segment && post != "health" && !post_slugs.contains_key(post) {
+                        report.error(file, format!("link to non-existent post '{}'", dest));
+                    }
+                }
+            }
+            Event::Start(Tag::Image(_, dest, _)) => {
+                if let Some(asset) = strip_fragment(&dest).strip_prefix("/static/")
+                    && !theme_dirs.has_static_file(asset)
+                {
+                    report.error(file, format!("image '{}' does not exist", dest));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// File names that are not valid slugs cannot be requested.
+fn requestable_slug(
+    report: &mut CheckReport,
+    content_dir: &ContentDir,
+    file: &SourceFile,
+) -> Option<Slug> {
+    match Slug::parse(&file.slug()) {
+        Ok(slug) => Some(slug),
+        Err(e) => {
+            report.error(
+                &relative_name(content_dir, file),
+                format!("{e}, not served"),
+            );
+            None
+        }
+    }
+}
+
+fn strip_fragment(dest: &str) -> &str {
+    dest.split(['#', '?']).next().unwrap_or(dest)
+}
+
+fn relative_name(content_dir: &ContentDir, file: &SourceFile) -> String {
+    let dir = content_dir.dir();
+    let path: &Path = file.path.strip_prefix(&dir).unwrap_or(&file.path);
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn content_with(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, content) in files {
+            let file = dir.path().join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        dir
+    }
+
+    async fn check(files: &[(&str, &str)]) -> CheckReport {
+        let dir = content_with(files);
+        check_blog(dir.path().to_path_buf(), PathBuf::from(".")).await
+    }
+
+    fn messages(report: &CheckReport) -> Vec<String> {
+        report
+            .issues
+            .iter()
+            .map(|issue| format!("{}: {}", issue.file, issue.message))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn valid_content_has_no_issues() {
+        let report = check(&[
+            (
+                "posts/hello.md",
+                "---\ntitle: Hello\ndatePublished: 2024-01-01\n---\n[About](/p/about)",
+            ),
+            ("pages/about.md", "---\ntitle: About\n---\n[Hello](/hello)"),
+        ])
+        .await;
+
+        assert!(report.issues.is_empty(), "{:?}", messages(&report));
+        assert!(!report.has_errors());
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_front_matter_and_dates() {
+        let report = check(&[
+            ("posts/broken.md", "---\ntitle: [unclosed\n---\n"),
+            (
+                "posts/odd-date.md",
+                "---\ntitle: Odd\ndatePublished: someday\n---\n",
+            ),
+        ])
+        .await;
+
+        let messages = messages(&report);
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|m| m.starts_with("posts/broken.md: front matter"))
+                .count(),
+            1
+        );
+        assert!(
+            report
+                .to_json()
+                .contains("front matter could not be parsed")
+        );
+        assert!(
+            messages.contains(
+                &"posts/odd-date.md: date 'someday' is not a recognised format".to_string()
+            )
+        );
+        assert!(report.has_errors());
+    }
+
+    #[tokio::test]
+    async fn reports_missing_title_as_warning() {
+        let report = check(&[("posts/untitled.md", "No front matter here.")]).await;
+
+        assert_eq!(report.errors(), 0);
+        assert_eq!(report.warnings(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_duplicate_slugs() {
+        let report = check(&[
+            ("posts/first.md", "---\ntitle: First\nslug: second\n---\n"),
+            ("posts/second.md", "---\ntitle: Second\n---\n"),
+        ])
+        .await;
+
+        assert!(
+            messages(&report)
+                .iter()
+                .any(|m| m.contains("slug 'second' is used by multiple posts"))
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_every_configured_content_dir() {
+        let dir = content_with(&[
+            ("posts/own.md", "---\ntitle: Own\n---\n[Shared](/shared)"),
+            ("posts/hello.md", "---\ntitle: Hello\n---\n"),
+            (
+                "shared/posts/shared.md",
+                "---\ntitle: Shared\n---\n[a](/missing)",
+            ),
+            ("shared/posts/hello.md", "---\ntitle: Hello again\n---\n"),
+        ]);
+        let mut config = BlogConfig::default();
+        config.storage.content_dirs.push(PathBuf::from("shared"));
+
+        let report = check_blog_with_config(dir.path(), Path::new("."), config).await;
+
+        assert_eq!(
+            messages(&report),
+            vec![
+                "shared/posts/hello.md: slug 'hello' is hidden by posts/hello.md",
+                "shared/posts/shared.md: link to non-existent post '/missing'",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn says_other_backends_are_not_checked() {
+        let dir = content_with(&[("posts/broken.md", "---\ntitle: [unclosed\n---\n")]);
+        let mut config = BlogConfig::default();
+        config.storage.backend = Backend::Sqlite;
+
+        let report = check_blog_with_config(dir.path(), Path::new("."), config).await;
+
+        assert_eq!(
+            messages(&report),
+            vec!["storage: content of the sqlite backend is not checked"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_broken_links_and_images() {
+        let report = check(&[(
+            "posts/links.md",
+            "---\ntitle: Links\n---\n[a](/missing) [b](/p/nope) ![c](/static/img/nope.png) [ok](https://example.com)",
+        )])
+        .await;
+
+        assert_eq!(
+            messages(&report),
+            vec![
+                "posts/links.md: link to non-existent post '/missing'",
+                "posts/links.md: link to non-existent page '/p/nope'",
+                "posts/links.md: image '/static/img/nope.png' does not exist",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_templates_that_fail_the_dry_run() {
+        let dir = content_with(&[
+            ("posts/post.md", "---\ntitle: Post\n---\n"),
+            ("templates/page.html", "{{ sidebar }}"),
+        ]);
+
+        let report = check_blog(dir.path(), dir.path()).await;
+
+        assert!(
+            messages(&report)
+                .iter()
+                .any(|m| m.starts_with("templates/page.html: ") && m.contains("sidebar")),
+            "{:?}",
+            messages(&report)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_code_blocks_in_unknown_languages() {
+        let report = check(&[(
+            "posts/code.md",
+            "---\ntitle: Code\n---\n```rust\n```\n```svgbob\n```\n```no-such-lang\n```\n",
+        )])
+        .await;
+
+        assert_eq!(
+            messages(&report),
+            vec![
+                "posts/code.md: code block language 'no-such-lang' is not known, it is not highlighted"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn report_serializes_to_json() {
+        let report = check(&[("posts/untitled.md", "")]).await;
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["issues"][0]["severity"], "warning");
+        assert_eq!(json["issues"][0]["file"], "posts/untitled.md");
+    }
+}
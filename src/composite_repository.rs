@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::Markdown;
+use crate::blog_repository::{BlogRepository, ChangeHook, RepositoryError, sort_posts};
+use crate::model::Revision;
+use crate::slug::Slug;
+
+type Source = Box<dyn BlogRepository + Send + Sync>;
+
+// Merges several repositories in priority order: the first source wins.
+// A post is left out entirely when any of its slugs belongs to a post of a
+// higher priority source, so lookups by any slug agree with the listing.
+pub struct CompositeBlogRepository {
+    sources: Vec<Source>,
+    merged: Mutex<Option<Arc<Merged>>>,
+    // Conflicts already reported, to warn once instead of on every lookup.
+    reported: Mutex<HashSet<String>>,
+}
+
+// The merge of one set of source listings. It is only built again when a
+// source lists different posts, which indexed sources do after a rescan.
+struct Merged {
+    listings: Vec<Arc<[Markdown]>>,
+    posts: Arc<[Markdown]>,
+    // Slug to the source of its post and the post's position in `posts`.
+    owners: HashMap<String, (usize, usize)>,
+}
+
+impl Merged {
+    fn is_of(&self, listings: &[Arc<[Markdown]>]) -> bool {
+        self.listings.len() == listings.len()
+            && self
+                .listings
+                .iter()
+                .zip(listings)
+                .all(|(merged, listing)| Arc::ptr_eq(merged, listing))
+    }
+}
+
+impl Default for CompositeBlogRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompositeBlogRepository {
+    pub fn new() -> Self {
+        CompositeBlogRepository {
+            sources: Vec::new(),
+            merged: Mutex::new(None),
+            reported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Added sources have lower priority than the ones before them.
+    pub fn with_source(mut self, source: impl BlogRepository + Send + Sync + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    fn merged(&self) -> Result<Arc<Merged>, RepositoryError> {
+        let listings = self
+            .sources
+            .iter()
+            .map(|source| source.get_all_posts())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut merged = self
+            .merged
+            .lock()
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+        if let Some(merged) = merged.as_ref()
+            && merged.is_of(&listings)
+        {
+            return Ok(merged.clone());
+        }
+        let rebuilt = Arc::new(self.merge(listings));
+        *merged = Some(rebuilt.clone());
+        Ok(rebuilt)
+    }
+
+    // Sorting happens after merging, so order does not depend on which
+    // source a post came from; ties keep priority order.
+    fn merge(&self, listings: Vec<Arc<[Markdown]>>) -> Merged {
+        let mut sources: HashMap<String, usize> = HashMap::new();
+        let mut posts = Vec::new();
+        for (priority, listing) in listings.iter().enumerate() {
+            for post in listing.iter() {
+                let taken = post
+                    .slugs
+                    .iter()
+                    .find_map(|slug| sources.get(slug).map(|owner| (slug, *owner)));
+                if let Some((slug, owner)) = taken {
+                    self.warn_once(format!(
+                        "Warning: Post '{slug}' of content source {} is hidden by source {}",
+                        priority + 1,
+                        owner + 1
+                    ));
+                    continue;
+                }
+                for slug in &post.slugs {
+                    sources.insert(slug.clone(), priority);
+                }
+                posts.push(post.clone());
+            }
+        }
+        sort_posts(&mut posts);
+
+        let mut owners = HashMap::new();
+        for (position, post) in posts.iter().enumerate() {
+            for slug in &post.slugs {
+                owners.insert(slug.clone(), (sources[slug], position));
+            }
+        }
+        Merged {
+            listings,
+            posts: posts.into(),
+            owners,
+        }
+    }
+
+    // Source of the post the slug resolves to, for revision lookups.
+    fn owner_of_post(&self, slug: &Slug) -> Result<Option<usize>, RepositoryError> {
+        let merged = self.merged()?;
+        Ok(merged.owners.get(slug.as_str()).map(|(source, _)| *source))
+    }
+
+    fn warn_once(&self, warning: String) {
+        let first_time = self
+            .reported
+            .lock()
+            .map(|mut reported| reported.insert(warning.clone()))
+            .unwrap_or(true);
+        if first_time {
+            eprintln!("{}", warning);
+        }
+    }
+}
+
+impl BlogRepository for CompositeBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        Ok(self.merged()?.posts.clone())
+    }
+
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let merged = self.merged()?;
+        Ok(merged
+            .owners
+            .get(slug.as_str())
+            .map(|(_, position)| merged.posts[*position].clone()))
+    }
+
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let mut found: Option<(usize, Markdown)> = None;
+        for (priority, source) in self.sources.iter().enumerate() {
+            let Some(page) = source.get_page(slug)? else {
+                continue;
+            };
+            match &found {
+                None => found = Some((priority, page)),
+                Some((owner, _)) => self.warn_once(format!(
+                    "Warning: Page '{slug}' of content source {} is hidden by source {}",
+                    priority + 1,
+                    owner + 1
+                )),
+            }
+        }
+        Ok(found.map(|(_, page)| page))
+    }
+
+    fn post_history(&self, slug: &Slug) -> Result<Option<Vec<Revision>>, RepositoryError> {
+        match self.owner_of_post(slug)? {
+            Some(owner) => self.sources[owner].post_history(slug),
+            None => Ok(None),
+        }
+    }
+
+    fn post_diff(
+        &self,
+        slug: &Slug,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        match self.owner_of_post(slug)? {
+            Some(owner) => self.sources[owner].post_diff(slug, from, to),
+            None => Ok(None),
+        }
+    }
+
+    fn on_change(&self, changed: ChangeHook) {
+        for source in &self.sources {
+            source.on_change(changed.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryBlogRepository;
+    use crate::slug::slug;
+
+    fn titles(posts: Arc<[Markdown]>) -> Vec<String> {
+        posts.iter().filter_map(|post| post.title.clone()).collect()
+    }
+
+    fn composite(sources: Vec<InMemoryBlogRepository>) -> CompositeBlogRepository {
+        sources
+            .into_iter()
+            .fold(CompositeBlogRepository::new(), |composite, source| {
+                composite.with_source(source)
+            })
+    }
+
+    #[test]
+    fn first_source_wins_on_duplicate_slugs() {
+        let repo = composite(vec![
+            InMemoryBlogRepository::new().with_post("hello", "---\ntitle: Own\n---\n"),
+            InMemoryBlogRepository::new()
+                .with_post("hello", "---\ntitle: Shared\n---\n")
+                .with_post("other", "---\ntitle: Shared Other\n---\n"),
+        ]);
+
+        assert_eq!(
+            titles(repo.get_all_posts().unwrap()),
+            vec!["Own", "Shared Other"]
+        );
+        let hello = repo.find_post_by_slug(&slug("hello")).unwrap().unwrap();
+        assert_eq!(hello.title.as_deref(), Some("Own"));
+    }
+
+    #[test]
+    fn hidden_post_is_not_found_by_its_other_slugs() {
+        let repo = composite(vec![
+            InMemoryBlogRepository::new().with_post("a", "---\ntitle: A\n---\n"),
+            InMemoryBlogRepository::new().with_post("b", "---\ntitle: B\nslug: a\n---\n"),
+        ]);
+
+        assert!(repo.find_post_by_slug(&slug("b")).unwrap().is_none());
+        assert_eq!(titles(repo.get_all_posts().unwrap()), vec!["A"]);
+    }
+
+    #[test]
+    fn sorts_merged_posts_like_a_single_source() {
+        let repo = composite(vec![
+            InMemoryBlogRepository::new()
+                .with_post("old", "---\ntitle: Old\ndatePublished: 2020-01-01\n---\n")
+                .with_post("z", "---\ntitle: Undated Z\n---\n"),
+            InMemoryBlogRepository::new()
+                .with_post("new", "---\ntitle: New\ndatePublished: 2022-01-01\n---\n")
+                .with_post("a", "---\ntitle: Undated A\n---\n"),
+        ]);
+
+        assert_eq!(
+            titles(repo.get_all_posts().unwrap()),
+            vec!["New", "Old", "Undated A", "Undated Z"]
+        );
+    }
+
+    #[test]
+    fn pages_fall_back_to_lower_priority_sources() {
+        let repo = composite(vec![
+            InMemoryBlogRepository::new().with_page("about", "Own about"),
+            InMemoryBlogRepository::new()
+                .with_page("about", "Shared about")
+                .with_page("imprint", "Shared imprint"),
+        ]);
+
+        assert_eq!(
+            repo.get_page(&slug("about")).unwrap().unwrap().content,
+            "Own about"
+        );
+        assert_eq!(
+            repo.get_page(&slug("imprint")).unwrap().unwrap().content,
+            "Shared imprint"
+        );
+        assert!(repo.get_page(&slug("missing")).unwrap().is_none());
+    }
+
+    // Lists the same posts until replaced and counts page lookups.
+    struct Listing {
+        posts: Arc<[Markdown]>,
+        page_lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    impl BlogRepository for Arc<Listing> {
+        fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+            Ok(self.posts.clone())
+        }
+
+        fn find_post_by_slug(&self, _: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+            Ok(None)
+        }
+
+        fn get_page(&self, _: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+            self.page_lookups
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(Some(Markdown::parse("Page")))
+        }
+    }
+
+    #[test]
+    fn merges_again_only_when_a_source_lists_other_posts() {
+        let mut post = Markdown::parse("---\ntitle: A\n---\n");
+        post.slugs.push("a".to_string());
+        let listing = Arc::new(Listing {
+            posts: vec![post].into(),
+            page_lookups: Default::default(),
+        });
+        let repo = CompositeBlogRepository::new()
+            .with_source(listing.clone())
+            .with_source(listing.clone());
+
+        let first = repo.get_all_posts().unwrap();
+        let found = repo.find_post_by_slug(&slug("a")).unwrap();
+        repo.get_page(&slug("about")).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &repo.get_all_posts().unwrap()));
+        assert_eq!(found.unwrap().title.as_deref(), Some("A"));
+        assert_eq!(
+            listing
+                .page_lookups
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::Write;
+
+use axum::body::Bytes;
+
+use crate::config::CompressionConfig;
+
+// Encoded variants of a cached html body, produced once when it is cached.
+#[derive(Clone, Debug)]
+pub struct Compressed {
+    pub gzip: Bytes,
+    pub brotli: Bytes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Identity => None,
+        }
+    }
+}
+
+impl Compressed {
+    pub fn new(body: &str, config: &CompressionConfig) -> Self {
+        Self {
+            gzip: gzip(body.as_bytes(), config.gzip_level),
+            brotli: brotli(body.as_bytes(), config.brotli_quality),
+        }
+    }
+
+    pub fn body_for(&self, encoding: Encoding) -> Option<Bytes> {
+        match encoding {
+            Encoding::Brotli => Some(self.brotli.clone()),
+            Encoding::Gzip => Some(self.gzip.clone()),
+            Encoding::Identity => None,
+        }
+    }
+}
+
+// Picks the encoding with the highest q-value from an Accept-Encoding header,
+// preferring brotli over gzip when both are equally acceptable.
+pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+    let Some(accept_encoding) = accept_encoding else {
+        return Encoding::Identity;
+    };
+
+    let mut brotli_q = None;
+    let mut gzip_q = None;
+    let mut wildcard_q = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "br" => brotli_q = Some(q),
+            "gzip" | "x-gzip" => gzip_q = Some(q),
+            "*" => wildcard_q = Some(q),
+            _ => {}
+        }
+    }
+
+    let brotli_q = brotli_q.or(wildcard_q).unwrap_or(0.0);
+    let gzip_q = gzip_q.or(wildcard_q).unwrap_or(0.0);
+    if brotli_q > 0.0 && brotli_q >= gzip_q {
+        Encoding::Brotli
+    } else if gzip_q > 0.0 {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+fn gzip(body: &[u8], level: u32) -> Bytes {
+    let mut encoder =
+        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+    encoder
+        .write_all(body)
+        .and_then(|_| encoder.finish())
+        .map(Bytes::from)
+        .expect("Writing to an in-memory gzip encoder should not fail")
+}
+
+fn brotli(body: &[u8], quality: u32) -> Bytes {
+    let mut output = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut output, 4096, quality.min(11), 22);
+        writer
+            .write_all(body)
+            .expect("Writing to an in-memory brotli encoder should not fail");
+    }
+    Bytes::from(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn negotiate_without_header_is_identity() {
+        assert_eq!(negotiate(None), Encoding::Identity);
+        assert_eq!(negotiate(Some("deflate")), Encoding::Identity);
+    }
+
+    #[test]
+    fn negotiate_prefers_brotli_on_equal_quality() {
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("*")), Encoding::Brotli);
+    }
+
+    #[test]
+    fn negotiate_respects_quality_values() {
+        assert_eq!(negotiate(Some("br;q=0.5, gzip")), Encoding::Gzip);
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0")), Encoding::Identity);
+        assert_eq!(negotiate(Some("*;q=0.1, br;q=0")), Encoding::Gzip);
+    }
+
+    #[test]
+    fn compressed_variants_decode_to_original() {
+        let html = "<p>hello hello hello</p>".repeat(20);
+        let compressed = Compressed::new(&html, &CompressionConfig::default());
+
+        let mut gunzipped = String::new();
+        flate2::read::GzDecoder::new(&compressed.gzip[..])
+            .read_to_string(&mut gunzipped)
+            .unwrap();
+        let mut unbrotlied = String::new();
+        brotli::Decompressor::new(&compressed.brotli[..], 4096)
+            .read_to_string(&mut unbrotlied)
+            .unwrap();
+
+        assert_eq!(gunzipped, html);
+        assert_eq!(unbrotlied, html);
+    }
+}
@@ -0,0 +1,207 @@
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+use crate::compression::{self, Encoding};
+use crate::renderer::RenderedHtml;
+
+// Answers with 304 Not Modified when the client's copy is still current,
+// otherwise with the html in the best encoding the client accepts.
+// Validators are sent in both cases.
+pub fn respond(request_headers: &HeaderMap, rendered: RenderedHtml) -> Response {
+    let encoding = match &rendered.compressed {
+        Some(_) => compression::negotiate(header_str(request_headers, header::ACCEPT_ENCODING)),
+        None => Encoding::Identity,
+    };
+    // Each encoding is a different representation and needs its own strong ETag.
+    let etag = rendered
+        .etag
+        .as_deref()
+        .map(|etag| etag_for_encoding(etag, encoding));
+
+    let last_modified = rendered.last_modified;
+
+    let mut response = validated(request_headers, etag.as_deref(), last_modified, || {
+        encoded_body(rendered, encoding)
+    });
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    response
+}
+
+// Like `respond`, for bodies served as they are, such as static files.
+pub fn validated(
+    request_headers: &HeaderMap,
+    etag: Option<&str>,
+    last_modified: Option<DateTime<Utc>>,
+    body: impl FnOnce() -> Response,
+) -> Response {
+    let mut response = if is_not_modified(request_headers, etag, last_modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        body()
+    };
+
+    let headers = response.headers_mut();
+    if let Some(etag) = etag.and_then(|e| HeaderValue::from_str(e).ok()) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Some(last_modified) =
+        last_modified.and_then(|date| HeaderValue::from_str(&http_date(date)).ok())
+    {
+        headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+    response
+}
+
+fn encoded_body(rendered: RenderedHtml, encoding: Encoding) -> Response {
+    let body = rendered
+        .compressed
+        .and_then(|compressed| compressed.body_for(encoding));
+    match (body, encoding.content_encoding()) {
+        (Some(body), Some(content_encoding)) => (
+            [
+                (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+                (header::CONTENT_ENCODING, content_encoding),
+            ],
+            body,
+        )
+            .into_response(),
+        _ => rendered.html.into_response(),
+    }
+}
+
+fn etag_for_encoding(etag: &str, encoding: Encoding) -> String {
+    match encoding.content_encoding() {
+        Some(suffix) => format!("{}-{}\"", etag.trim_end_matches('"'), suffix),
+        None => etag.to_string(),
+    }
+}
+
+fn is_not_modified(
+    request_headers: &HeaderMap,
+    etag: Option<&str>,
+    last_modified: Option<DateTime<Utc>>,
+) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since (RFC 9110, 13.2.2).
+    if let Some(if_none_match) = header_str(request_headers, header::IF_NONE_MATCH) {
+        return etag.is_some_and(|etag| etag_matches(if_none_match, etag));
+    }
+
+    match (
+        header_str(request_headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date),
+        last_modified,
+    ) {
+        (Some(since), Some(last_modified)) => last_modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|candidate| candidate.trim().trim_start_matches("W/"))
+            .any(|candidate| candidate == etag)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn http_date(date: DateTime<Utc>) -> String {
+    httpdate::fmt_http_date(SystemTime::from(date))
+}
+
+pub(crate) fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    httpdate::parse_http_date(value).ok().map(DateTime::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compressed;
+    use crate::config::CompressionConfig;
+    use axum::response::Html;
+    use chrono::TimeZone;
+
+    fn rendered() -> RenderedHtml {
+        RenderedHtml {
+            html: Html("<p>hello</p>".to_string()),
+            last_modified: Some(Utc.with_ymd_and_hms(2024, 1, 2, 10, 0, 0).unwrap()),
+            etag: Some("\"abc\"".to_string()),
+            compressed: None,
+        }
+    }
+
+    fn request(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn sends_validators_with_full_response() {
+        let response = respond(&HeaderMap::new(), rendered());
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"abc\"");
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Tue, 02 Jan 2024 10:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let headers = request(header::IF_NONE_MATCH, "\"other\", \"abc\"");
+
+        let response = respond(&headers, rendered());
+
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"abc\"");
+    }
+
+    #[test]
+    fn different_etag_wins_over_if_modified_since() {
+        let mut headers = request(header::IF_NONE_MATCH, "\"other\"");
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Wed, 03 Jan 2024 10:00:00 GMT"),
+        );
+
+        assert_eq!(respond(&headers, rendered()).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn compressed_response_has_encoding_specific_etag() {
+        let rendered = RenderedHtml {
+            compressed: Some(Compressed::new(
+                "<p>hello</p>",
+                &CompressionConfig::default(),
+            )),
+            ..rendered()
+        };
+        let headers = request(header::ACCEPT_ENCODING, "gzip");
+
+        let response = respond(&headers, rendered);
+
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::ETAG], "\"abc-gzip\"");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+    }
+
+    #[test]
+    fn if_modified_since_compares_last_modified() {
+        let current = request(header::IF_MODIFIED_SINCE, "Tue, 02 Jan 2024 10:00:00 GMT");
+        let stale = request(header::IF_MODIFIED_SINCE, "Mon, 01 Jan 2024 10:00:00 GMT");
+
+        assert_eq!(
+            respond(&current, rendered()).status(),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(respond(&stale, rendered()).status(), StatusCode::OK);
+    }
+}
@@ -1,10 +1,177 @@
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::highlight;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BlogConfig {
     pub site_title: String,
     pub site_description: String,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    #[serde(default)]
+    pub highlighting: HighlightingConfig,
+    // Name of a directory below `themes/` in the blog directory, or a path.
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    // Free-form settings for themes, available to templates as `extra`.
+    #[serde(default)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+pub(crate) const DEFAULT_THEME: &str = "default";
+
+fn default_theme() -> String {
+    DEFAULT_THEME.to_string()
+}
+
+// Where posts and pages are read from. A relative `sqlite_path` is resolved
+// against the content directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    // Further content directories for the files backend, e.g. posts shared
+    // between blogs. Earlier directories win on duplicate slugs; relative
+    // paths are resolved against the main content directory.
+    pub content_dirs: Vec<PathBuf>,
+    pub sqlite_path: PathBuf,
+    pub s3: Option<S3Config>,
+}
+
+// An S3-compatible bucket holding `posts/`, `pages/` and `static/` below
+// `prefix`. Credentials left out here are read from AWS_ACCESS_KEY_ID and
+// AWS_SECRET_ACCESS_KEY; without any, requests are sent unsigned.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    // Bucket in the path as MinIO expects, instead of in the host name.
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    // How often the bucket listing is compared against the known ETags.
+    #[serde(default = "default_poll_seconds")]
+    pub poll_seconds: u64,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+fn default_poll_seconds() -> u64 {
+    60
+}
+
+// How templates are checked at startup. `strict` refuses to start when a
+// template is missing, does not compile or fails the dry-run render with
+// sample data; `lenient` logs a warning per issue and serves anyway.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    pub validation: TemplateValidation,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateValidation {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+// Syntax highlighting of code blocks. `inline` writes the colors of `theme`
+// into style attributes; `classes` emits CSS classes styled by a stylesheet
+// served at /_highlight/theme.css, which switches to `dark_theme` when the
+// visitor prefers a dark color scheme. Themes are names of syntect's
+// built-in themes or paths of `.tmTheme` files in the blog directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HighlightingConfig {
+    pub mode: HighlightMode,
+    pub theme: String,
+    pub dark_theme: Option<String>,
+    // Extra `.sublime-syntax` files, relative to the blog directory.
+    pub syntaxes_dir: PathBuf,
+    // Code block languages mapped to a syntax name or token, like
+    // `tf: Terraform` or `console: sh`.
+    pub aliases: BTreeMap<String, String>,
+    // Where the syntax set compiled with the extra syntaxes is kept between
+    // starts, relative to the blog directory; `$XDG_CACHE_HOME/blog-engine`
+    // (or `~/.cache/blog-engine`) when unset.
+    pub cache_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    #[default]
+    Inline,
+    Classes,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Files,
+    Sqlite,
+    S3,
+}
+
+// Levels used when pre-compressing cached pages: gzip 0-9, brotli 0-11.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub gzip_level: u32,
+    pub brotli_quality: u32,
+}
+
+// Limits for the rendered html cache. Unset limits mean unbounded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub ttl_seconds: TtlConfig,
+    // Render every post in the background at startup.
+    pub preload: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_entries: None,
+            max_bytes: None,
+            ttl_seconds: TtlConfig::default(),
+            preload: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TtlConfig {
+    pub post: Option<u64>,
+    pub page: Option<u64>,
+    pub index: Option<u64>,
 }
 
 impl Default for BlogConfig {
@@ -12,6 +179,80 @@ impl Default for BlogConfig {
         BlogConfig {
             site_title: "Your Blog".to_string(),
             site_description: "Your blog description".to_string(),
+            compression: CompressionConfig::default(),
+            cache: CacheConfig::default(),
+            storage: StorageConfig::default(),
+            templates: TemplatesConfig::default(),
+            highlighting: HighlightingConfig::default(),
+            theme: default_theme(),
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: Backend::Files,
+            content_dirs: Vec::new(),
+            sqlite_path: PathBuf::from("blog.db"),
+            s3: None,
+        }
+    }
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        TemplatesConfig {
+            validation: TemplateValidation::Lenient,
+            dry_run: true,
+        }
+    }
+}
+
+impl Default for HighlightingConfig {
+    fn default() -> Self {
+        HighlightingConfig {
+            mode: HighlightMode::Inline,
+            theme: highlight::DEFAULT_THEME.to_string(),
+            dark_theme: None,
+            syntaxes_dir: PathBuf::from("syntaxes"),
+            aliases: BTreeMap::new(),
+            cache_dir: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::Files => "files",
+            Backend::Sqlite => "sqlite",
+            Backend::S3 => "s3",
+        })
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "files" => Ok(Backend::Files),
+            "sqlite" => Ok(Backend::Sqlite),
+            "s3" => Ok(Backend::S3),
+            other => Err(format!(
+                "Unknown storage backend '{other}', expected files, sqlite or s3"
+            )),
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            gzip_level: 6,
+            brotli_quality: 9,
         }
     }
 }
@@ -40,6 +281,16 @@ impl BlogConfig {
         }
     }
 
+    pub fn from_yaml_or_default(yaml: &str) -> Self {
+        match serde_yaml::from_str(yaml) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Warning: Could not parse config: {e}. Using default values.");
+                Self::default()
+            }
+        }
+    }
+
     pub fn from_file_or_default<P: AsRef<Path> + std::fmt::Debug + Clone>(path: P) -> Self {
         match Self::from_file(path) {
             Ok(config) => config,
@@ -52,3 +303,22 @@ impl BlogConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_compression_section_keeps_other_settings() {
+        let config = BlogConfig::from_yaml_or_default(
+            "site_title: Mine\nsite_description: Blog\ncompression:\n  gzip_level: 4\n",
+        );
+
+        assert_eq!(config.site_title, "Mine");
+        assert_eq!(config.compression.gzip_level, 4);
+        assert_eq!(
+            config.compression.brotli_quality,
+            CompressionConfig::default().brotli_quality
+        );
+    }
+}
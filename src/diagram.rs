@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+use svgbob::{CellBuffer, Node, Settings};
+
+use crate::highlight::CodeBlockInfo;
+
+// Fenced code blocks in these languages are drawn as diagrams.
+pub(crate) const LANGUAGES: &[&str] = &["svgbob", "bob"];
+
+// Diagrams rarely change between renders of a post, so the svg is kept by
+// a hash of the source. The cache starts over when it is full, which only
+// happens after many edits.
+const MAX_CACHED: usize = 512;
+
+#[derive(Default)]
+pub(crate) struct DiagramCache {
+    svgs: Mutex<HashMap<[u8; 32], Arc<str>>>,
+}
+
+pub(crate) fn is_diagram(info: &CodeBlockInfo) -> bool {
+    info.lang
+        .as_deref()
+        .is_some_and(|lang| LANGUAGES.contains(&lang))
+}
+
+impl DiagramCache {
+    // The diagram as a figure, with the block's title as caption.
+    pub fn figure(&self, source: &str, info: &CodeBlockInfo) -> String {
+        let caption = info
+            .title
+            .as_deref()
+            .map(|title| format!("<figcaption>{}</figcaption>", escape(title)))
+            .unwrap_or_default();
+        format!(
+            "<figure class=\"diagram\">{}{}</figure>",
+            self.svg(source),
+            caption
+        )
+    }
+
+    pub fn svg(&self, source: &str) -> Arc<str> {
+        let key: [u8; 32] = Sha256::digest(source.as_bytes()).into();
+
+        if let Some(svg) = self.lock().get(&key) {
+            return svg.clone();
+        }
+        let svg: Arc<str> = to_svg(source).into();
+        let mut svgs = self.lock();
+        if svgs.len() >= MAX_CACHED {
+            svgs.clear();
+        }
+        svgs.insert(key, svg.clone());
+        svg
+    }
+
+    // A poisoned lock only means a render panicked; the entries are fine.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<[u8; 32], Arc<str>>> {
+        self.svgs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Strokes and text use `currentColor` on a transparent background, so the
+// diagram follows the site's css.
+pub(crate) fn to_svg(source: &str) -> String {
+    let settings = Settings {
+        fill_color: "currentColor".into(),
+        stroke_color: "currentColor".into(),
+        background: "transparent".into(),
+        font_family: "monospace".into(),
+        include_backdrop: false,
+        ..Settings::default()
+    };
+    let (svg, _, _): (Node<()>, f32, f32) = CellBuffer::from(source).get_node_with_size(&settings);
+    svg.render_to_string()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark::escape::escape_html(&mut escaped, text).unwrap_or_default();
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_boxes_arrows_and_labels() {
+        let svg = to_svg(".---.    +------+\n| a |--->| b  c |\n'---'    +------+");
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.contains("class=\"svgbob\""));
+        assert!(svg.contains("<rect x=\"76\" y=\"8\" width=\"56\" height=\"32\""));
+        assert!(svg.contains("<polygon points=\"64,20 72,24 64,28\" class=\"filled\">"));
+        assert!(svg.contains(">a</text>") && svg.contains(">c</text>"));
+        assert!(svg.contains("stroke: currentColor;"));
+    }
+
+    #[test]
+    fn escapes_text() {
+        let svg = to_svg("1 < 2 & <b>");
+
+        assert!(svg.contains(">&lt;b&gt;</text>"), "{svg}");
+        assert!(!svg.contains("<b>"));
+    }
+
+    #[test]
+    fn cache_reuses_the_svg_of_the_same_source() {
+        let cache = DiagramCache::default();
+
+        let first = cache.svg("+--+");
+        let second = cache.svg("+--+");
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &cache.svg("+---+")));
+    }
+}
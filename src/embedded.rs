@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use include_dir::{Dir, include_dir};
+use tera::Tera;
+
+use crate::InMemoryBlogRepository;
+use crate::builtin;
+use crate::compression::{self, Encoding};
+use crate::config::BlogConfig;
+use crate::highlight::{self, Highlighter, SyntaxFile};
+use crate::template_validation::TemplateIssue;
+use crate::theme::ThemeMetadata;
+
+// The content directory assembled by `just collect-deploy-assets`: posts,
+// pages, themes, site templates and static files, and blog_config.yaml.
+pub(crate) static CONTENT: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/content");
+
+pub(crate) fn config(content: &'static Dir<'static>) -> BlogConfig {
+    content
+        .get_file("blog_config.yaml")
+        .and_then(|file| file.contents_utf8())
+        .map(BlogConfig::from_yaml_or_default)
+        .unwrap_or_default()
+}
+
+pub(crate) fn repository(content: &'static Dir<'static>) -> InMemoryBlogRepository {
+    let repo = InMemoryBlogRepository::new();
+    for (name, markdown) in content
+        .get_dir("posts")
+        .into_iter()
+        .flat_map(markdown_files)
+    {
+        repo.add_post(name, markdown);
+    }
+    for (name, markdown) in content
+        .get_dir("pages")
+        .into_iter()
+        .flat_map(markdown_files)
+    {
+        repo.add_page(name, markdown);
+    }
+    repo
+}
+
+// The theme selected in the config, checked like a theme on disk.
+pub(crate) fn theme(
+    content: &'static Dir<'static>,
+    config: &BlogConfig,
+) -> Option<&'static Dir<'static>> {
+    let dir = Path::new("themes").join(&config.theme);
+    let Some(theme) = content.get_dir(&dir) else {
+        eprintln!("Warning: theme directory {} not embedded", dir.display());
+        return None;
+    };
+    let metadata = theme
+        .get_file(dir.join("theme.yaml"))
+        .and_then(|file| file.contents_utf8())
+        .ok_or_else(|| "theme.yaml is missing".to_string())
+        .and_then(ThemeMetadata::parse);
+    match metadata.map(|metadata| metadata.check_config(config)) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Warning: {e}"),
+        Err(e) => eprintln!("Warning: theme {} is invalid: {e}", dir.display()),
+    }
+    Some(theme)
+}
+
+// Custom `.tmTheme` and `.sublime-syntax` files are looked up in the
+// embedded content directory.
+pub(crate) fn highlighter(content: &'static Dir<'static>, config: &BlogConfig) -> Highlighter {
+    let syntaxes: Vec<SyntaxFile> = content
+        .get_dir(&config.highlighting.syntaxes_dir)
+        .map(|dir| {
+            dir.files()
+                .filter(|file| {
+                    file.path()
+                        .extension()
+                        .is_some_and(|ext| ext == "sublime-syntax")
+                })
+                .filter_map(|file| {
+                    let name = file.path().file_name()?.to_string_lossy().into_owned();
+                    Some((name, file.contents_utf8()?.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // A relative cache directory is taken from the working directory.
+    let cache_dir = highlight::cache_dir(&config.highlighting, Path::new(""));
+    Highlighter::with_files(
+        &config.highlighting,
+        &syntaxes,
+        cache_dir.as_deref(),
+        |path| content.get_file(path).map(|file| file.contents().to_vec()),
+    )
+}
+
+// Template names are relative to `templates`, as when loading them from
+// disk. Site templates override the theme's, which are also available as
+// `theme/<name>`, and both override the built-in ones.
+pub(crate) fn templates(
+    site: Option<&'static Dir<'static>>,
+    theme: Option<&'static Dir<'static>>,
+) -> Tera {
+    let mut sources: BTreeMap<String, &'static str> = BTreeMap::new();
+    for (name, source) in theme.into_iter().flat_map(template_sources) {
+        sources.insert(format!("theme/{name}"), source);
+        sources.insert(name, source);
+    }
+    sources.extend(site.into_iter().flat_map(template_sources));
+
+    let mut tera = builtin::templates();
+    if let Err(e) = tera.add_raw_templates(sources) {
+        TemplateIssue::parse(&e).warn();
+        log::warn!("Serving the built-in templates instead");
+        return builtin::templates();
+    }
+    tera
+}
+
+// Paths of embedded directories are relative to the embedded root.
+pub(crate) fn subdir(dir: &'static Dir<'static>, name: &str) -> Option<&'static Dir<'static>> {
+    dir.get_dir(dir.path().join(name))
+}
+
+fn template_sources(templates: &'static Dir<'static>) -> Vec<(String, &'static str)> {
+    files_in(templates)
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.path().strip_prefix(templates.path()).ok()?.to_str()?;
+            Some((name.to_string(), file.contents_utf8()?))
+        })
+        .collect()
+}
+
+// Serves files from the first of the static directories that has them,
+// preferring a precompressed `.br` or `.gz` sibling when the client accepts
+// it, like `ServeDir` does on disk.
+pub(crate) fn serve_static(
+    dirs: &[&'static Dir<'static>],
+    path: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let found = dirs.iter().find_map(|statics| {
+        let path = statics.path().join(path.trim_start_matches('/'));
+        Some((*statics, statics.get_file(&path)?, path))
+    });
+    let Some((statics, file, path)) = found else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let accept_encoding = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    let encoding = compression::negotiate(accept_encoding);
+    let precompressed = match encoding {
+        Encoding::Brotli => statics.get_file(path.with_added_extension("br")),
+        Encoding::Gzip => statics.get_file(path.with_added_extension("gz")),
+        Encoding::Identity => None,
+    };
+
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+    let mut response = match (precompressed, encoding.content_encoding()) {
+        (Some(compressed), Some(content_encoding)) => (
+            [(header::CONTENT_ENCODING, content_encoding)],
+            compressed.contents(),
+        )
+            .into_response(),
+        _ => file.contents().into_response(),
+    };
+    if let Ok(content_type) = HeaderValue::from_str(content_type.as_ref()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    response
+}
+
+fn markdown_files(
+    dir: &'static Dir<'static>,
+) -> impl Iterator<Item = (&'static str, &'static str)> {
+    dir.files()
+        .filter(|file| file.path().extension().is_some_and(|ext| ext == "md"))
+        .filter_map(|file| {
+            let name = file.path().file_stem()?.to_str()?;
+            Some((name, file.contents_utf8()?))
+        })
+}
+
+fn files_in(dir: &'static Dir<'static>) -> Vec<&'static include_dir::File<'static>> {
+    let nested = dir.dirs().flat_map(files_in);
+    dir.files().chain(nested).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The blog's own sources, so the tests do not depend on collected assets.
+    static THEME: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/themes/default");
+    static PAGES: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/pages");
+
+    #[test]
+    fn loads_theme_templates_by_relative_name() {
+        let tera = templates(None, subdir(&THEME, "templates"));
+
+        assert!(tera.get_template_names().any(|name| name == "post.html"));
+        assert!(
+            tera.get_template_names()
+                .any(|name| name == "partials/header.html")
+        );
+        assert!(
+            tera.get_template_names()
+                .any(|name| name == "theme/post.html")
+        );
+    }
+
+    #[test]
+    fn loads_markdown_files_by_name() {
+        let pages: Vec<&str> = markdown_files(&PAGES).map(|(name, _)| name).collect();
+
+        assert!(pages.contains(&"about"));
+    }
+
+    #[test]
+    fn serves_static_files_with_content_type() {
+        let statics = [subdir(&THEME, "static").unwrap()];
+        let response = serve_static(&statics, "/css/main.css", &HeaderMap::new());
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert_eq!(
+            serve_static(&statics, "/missing.css", &HeaderMap::new()).status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
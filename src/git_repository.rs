@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use git2::{ObjectType, Oid, Repository, Sort, Tree};
+
+use crate::Markdown;
+use crate::blog_repository::{BlogRepository, RepositoryError, sort_posts};
+use crate::cache::CachedRenderer;
+use crate::config::BlogConfig;
+use crate::model::Revision;
+use crate::slug::Slug;
+
+// Reads posts and pages straight from the object database of a git
+// repository at a configurable ref, so no working tree is needed.
+#[derive(Clone)]
+pub(crate) struct GitBlogRepository {
+    inner: Arc<GitSource>,
+}
+
+struct GitSource {
+    repo: Mutex<Repository>,
+    reference: RwLock<String>,
+    // Revisions per file for the most recently resolved commit.
+    history: Mutex<Option<(Oid, Arc<FileRevisions>)>>,
+}
+
+// Markdown path to the commits that changed it, oldest first.
+type FileRevisions = HashMap<String, Vec<Revision>>;
+
+impl GitBlogRepository {
+    pub fn open(repo_path: &Path, reference: &str) -> Result<Self, std::io::Error> {
+        let repo = Repository::open(repo_path).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "Git repository {} could not be opened: {e}",
+                    repo_path.display()
+                ),
+            )
+        })?;
+        let git = GitBlogRepository {
+            inner: Arc::new(GitSource {
+                repo: Mutex::new(repo),
+                reference: RwLock::new(reference.to_string()),
+                history: Mutex::new(None),
+            }),
+        };
+        git.set_reference(reference)?;
+        Ok(git)
+    }
+
+    pub fn reference(&self) -> String {
+        self.inner
+            .reference
+            .read()
+            .map(|reference| reference.clone())
+            .unwrap_or_default()
+    }
+
+    // Only switches when the ref resolves to a commit.
+    pub fn set_reference(&self, reference: &str) -> Result<(), std::io::Error> {
+        let invalid_ref = |e: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Git ref '{reference}' could not be resolved: {e}"),
+            )
+        };
+        {
+            let repo = self
+                .lock_repo()
+                .map_err(|_| invalid_ref("lock poisoned".into()))?;
+            repo.revparse_single(reference)
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|e| invalid_ref(e.message().to_string()))?;
+        }
+        let mut current = self
+            .inner
+            .reference
+            .write()
+            .map_err(|_| invalid_ref("lock poisoned".into()))?;
+        *current = reference.to_string();
+        Ok(())
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Option<String>, RepositoryError> {
+        let repo = self.lock_repo()?;
+        let tree = self.resolve_tree(&repo)?;
+        blob_content(&repo, &tree, Path::new(path))
+    }
+
+    // `blog_config.yaml` at the current ref.
+    pub fn config(&self) -> BlogConfig {
+        match self.read_file("blog_config.yaml") {
+            Ok(Some(yaml)) => BlogConfig::from_yaml_or_default(&yaml),
+            _ => BlogConfig::default(),
+        }
+    }
+
+    fn lock_repo(&self) -> Result<std::sync::MutexGuard<'_, Repository>, RepositoryError> {
+        self.inner
+            .repo
+            .lock()
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+
+    fn resolve_commit<'r>(
+        &self,
+        repo: &'r Repository,
+    ) -> Result<git2::Commit<'r>, RepositoryError> {
+        repo.revparse_single(&self.reference())
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|_| RepositoryError::NotFound)
+    }
+
+    fn resolve_tree<'r>(&self, repo: &'r Repository) -> Result<Tree<'r>, RepositoryError> {
+        self.resolve_commit(repo)?
+            .tree()
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+
+    fn all_posts_unsorted(&self) -> Result<Vec<Markdown>, RepositoryError> {
+        let posts = self.posts_with_paths()?;
+        Ok(posts.into_iter().map(|(_, markdown)| markdown).collect())
+    }
+
+    fn post_path(&self, slug: &Slug) -> Result<Option<String>, RepositoryError> {
+        Ok(self
+            .posts_with_paths()?
+            .into_iter()
+            .find(|(_, markdown)| markdown.contains(slug.to_string()))
+            .map(|(path, _)| path))
+    }
+
+    fn posts_with_paths(&self) -> Result<Vec<(String, Markdown)>, RepositoryError> {
+        let repo = self.lock_repo()?;
+        let commit = self.resolve_commit(&repo)?;
+        let tree = commit
+            .tree()
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+        let histories = self.revisions(&repo, &commit)?;
+
+        let Ok(posts) = tree.get_path(Path::new("posts")) else {
+            return Ok(Vec::new());
+        };
+        let posts = posts
+            .to_object(&repo)
+            .and_then(|object| object.peel_to_tree())
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+
+        let mut markdowns = Vec::new();
+        for entry in posts.iter() {
+            let Some(name) = entry.name().filter(|name| name.ends_with(".md")) else {
+                continue;
+            };
+            if entry.kind() != Some(ObjectType::Blob) {
+                continue;
+            }
+            let path = format!("posts/{}", name);
+            let Some(content) = blob_content(&repo, &tree, Path::new(&path))? else {
+                continue;
+            };
+
+            let mut markdown = Markdown::parse(&content);
+            markdown
+                .slugs
+                .push(name.trim_end_matches(".md").to_string());
+            with_history(&mut markdown, histories.get(&path));
+            markdowns.push((path, markdown));
+        }
+        Ok(markdowns)
+    }
+
+    fn revisions(
+        &self,
+        repo: &Repository,
+        commit: &git2::Commit,
+    ) -> Result<Arc<FileRevisions>, RepositoryError> {
+        let mut cached = self
+            .inner
+            .history
+            .lock()
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+        if let Some((oid, histories)) = cached.as_ref()
+            && *oid == commit.id()
+        {
+            return Ok(histories.clone());
+        }
+
+        let histories =
+            Arc::new(file_revisions(repo, commit).map_err(|_| RepositoryError::UnexpectedError)?);
+        *cached = Some((commit.id(), histories.clone()));
+        Ok(histories)
+    }
+}
+
+fn with_history(markdown: &mut Markdown, revisions: Option<&Vec<Revision>>) {
+    let (Some(created), Some(updated)) = (
+        revisions.and_then(|r| r.first()),
+        revisions.and_then(|r| r.last()),
+    ) else {
+        return;
+    };
+    // Dates written in the front matter win over the history.
+    markdown
+        .publish_date
+        .get_or_insert(created.date.date_naive());
+    markdown.modified.get_or_insert(updated.date);
+    markdown.updated.get_or_insert(updated.date);
+}
+
+// Walks the history oldest-first and records, for every markdown file in
+// the commit's tree, the commits that changed its content. A merge only
+// counts when the file differs from every parent, like in `git log`.
+fn file_revisions(repo: &Repository, head: &git2::Commit) -> Result<FileRevisions, git2::Error> {
+    let head_tree = head.tree()?;
+    let tracked: Vec<String> = ["posts", "pages"]
+        .iter()
+        .flat_map(|dir| markdown_paths(repo, &head_tree, dir))
+        .collect();
+
+    let mut walk = repo.revwalk()?;
+    walk.push(head.id())?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME | Sort::REVERSE)?;
+
+    let mut revisions = FileRevisions::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent_trees = commit
+            .parents()
+            .map(|parent| parent.tree())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for path in &tracked {
+            let current = blob_id(&tree, path);
+            let changed = parent_trees
+                .iter()
+                .all(|parent| blob_id(parent, path) != current);
+            if current.is_some() && changed {
+                revisions.entry(path.clone()).or_default().push(Revision {
+                    hash: commit.id().to_string(),
+                    message: commit.summary().unwrap_or_default().to_string(),
+                    date: commit_time(&commit),
+                });
+            }
+        }
+    }
+    Ok(revisions)
+}
+
+fn markdown_paths(repo: &Repository, tree: &Tree, dir: &str) -> Vec<String> {
+    tree.get_path(Path::new(dir))
+        .and_then(|entry| entry.to_object(repo))
+        .and_then(|object| object.peel_to_tree())
+        .map(|subtree| {
+            subtree
+                .iter()
+                .filter_map(|entry| entry.name().map(str::to_string))
+                .filter(|name| name.ends_with(".md"))
+                .map(|name| format!("{}/{}", dir, name))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn blob_id(tree: &Tree, path: &str) -> Option<Oid> {
+    tree.get_path(Path::new(path)).ok().map(|entry| entry.id())
+}
+
+fn blob_content(
+    repo: &Repository,
+    tree: &Tree,
+    path: &Path,
+) -> Result<Option<String>, RepositoryError> {
+    let Ok(entry) = tree.get_path(path) else {
+        return Ok(None);
+    };
+    let blob = entry
+        .to_object(repo)
+        .and_then(|object| object.peel_to_blob())
+        .map_err(|_| RepositoryError::UnexpectedError)?;
+    String::from_utf8(blob.content().to_vec())
+        .map(Some)
+        .map_err(|_| RepositoryError::UnexpectedError)
+}
+
+fn commit_time(commit: &git2::Commit) -> DateTime<Utc> {
+    DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default()
+}
+
+impl BlogRepository for GitBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        let mut markdowns = self.all_posts_unsorted()?;
+        sort_posts(&mut markdowns);
+        Ok(markdowns.into())
+    }
+
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let markdowns = self.all_posts_unsorted()?;
+        Ok(markdowns
+            .into_iter()
+            .find(|markdown| markdown.contains(slug.to_string())))
+    }
+
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let path = format!("pages/{}.md", slug);
+        let repo = self.lock_repo()?;
+        let commit = self.resolve_commit(&repo)?;
+        let tree = commit
+            .tree()
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+        let Some(content) = blob_content(&repo, &tree, Path::new(&path))? else {
+            return Ok(None);
+        };
+        let updated = self
+            .revisions(&repo, &commit)?
+            .get(&path)
+            .and_then(|revisions| revisions.last())
+            .map(|revision| revision.date);
+
+        Ok(Some(Markdown {
+            title: None,
+            content,
+            slugs: vec![slug.to_string()],
+            publish_date: None,
+            modified: updated,
+            updated,
+            front_matter: None,
+        }))
+    }
+
+    fn post_history(&self, slug: &Slug) -> Result<Option<Vec<Revision>>, RepositoryError> {
+        let Some(path) = self.post_path(slug)? else {
+            return Ok(None);
+        };
+        let repo = self.lock_repo()?;
+        let commit = self.resolve_commit(&repo)?;
+        let revisions = self.revisions(&repo, &commit)?;
+        let newest_first = revisions
+            .get(&path)
+            .map(|revisions| revisions.iter().rev().cloned().collect())
+            .unwrap_or_default();
+        Ok(Some(newest_first))
+    }
+
+    fn post_diff(
+        &self,
+        slug: &Slug,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        let Some(path) = self.post_path(slug)? else {
+            return Ok(None);
+        };
+        let repo = self.lock_repo()?;
+        let commit = self.resolve_commit(&repo)?;
+        // Only revisions from the post's history can be compared, so branches,
+        // tags or other commits with unpublished content cannot be read.
+        let revisions = self.revisions(&repo, &commit)?;
+        let revision = |hash: &str| {
+            revisions
+                .get(&path)?
+                .iter()
+                .find(|revision| revision.hash == hash)
+                .and_then(|revision| Oid::from_str(&revision.hash).ok())
+        };
+        let (Some(from), Some(to)) = (revision(from), revision(to)) else {
+            return Ok(None);
+        };
+        diff_file(&repo, &path, from, to)
+            .map(Some)
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+}
+
+// Unified diff of a single file between two commits.
+fn diff_file(repo: &Repository, path: &str, from: Oid, to: Oid) -> Result<String, git2::Error> {
+    let (from, to) = (repo.find_commit(from)?, repo.find_commit(to)?);
+
+    let mut options = git2::DiffOptions::new();
+    options.pathspec(path);
+    let diff =
+        repo.diff_tree_to_tree(Some(&from.tree()?), Some(&to.tree()?), Some(&mut options))?;
+
+    let mut patch = String::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin());
+        }
+        patch.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    })?;
+    Ok(patch)
+}
+
+// Handle to switch the served git ref at runtime. Switching drops all
+// cached html so the next requests render the new content.
+#[derive(Clone)]
+pub struct GitContent {
+    repo: GitBlogRepository,
+    cache: CachedRenderer,
+}
+
+impl GitContent {
+    pub(crate) fn new(repo: GitBlogRepository, cache: CachedRenderer) -> Self {
+        GitContent { repo, cache }
+    }
+
+    pub fn reference(&self) -> String {
+        self.repo.reference()
+    }
+
+    // The configuration the content was served with, read at the current ref.
+    pub fn config(&self) -> BlogConfig {
+        self.repo.config()
+    }
+
+    pub async fn switch_ref(&self, reference: &str) -> Result<(), std::io::Error> {
+        self.repo.set_reference(reference)?;
+        self.cache.invalidate();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use git2::{Repository, Signature, Time};
+    use std::path::Path;
+
+    // Commits the given files on top of HEAD at a fixed point in time.
+    pub fn commit_files(repo: &Repository, files: &[(&str, &str)], seconds: i64, message: &str) {
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            let file = workdir.join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, content).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature =
+            Signature::new("Author", "author@example.com", &Time::new(seconds, 0)).unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::commit_files;
+    use super::*;
+    use crate::slug::slug;
+    use tempfile::TempDir;
+
+    const DAY: i64 = 24 * 60 * 60;
+    const JAN_1_2024: i64 = 1_704_067_200;
+
+    fn git_repo() -> (TempDir, Repository) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn reads_posts_and_pages_at_ref() {
+        let (dir, repo) = git_repo();
+        commit_files(
+            &repo,
+            &[
+                ("posts/hello.md", "---\ntitle: Hello\n---\nHi"),
+                ("pages/about.md", "# About"),
+            ],
+            JAN_1_2024,
+            "Initial content",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+
+        let post = git.find_post_by_slug(&slug("hello")).unwrap().unwrap();
+        assert_eq!(post.title.as_deref(), Some("Hello"));
+        assert_eq!(
+            git.get_page(&slug("about")).unwrap().unwrap().content,
+            "# About"
+        );
+        assert!(git.get_page(&slug("missing")).unwrap().is_none());
+    }
+
+    #[test]
+    fn derives_dates_from_history_when_front_matter_lacks_them() {
+        let (dir, repo) = git_repo();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: A\n---\nv1")],
+            JAN_1_2024,
+            "Add a",
+        );
+        commit_files(
+            &repo,
+            &[(
+                "posts/b.md",
+                "---\ntitle: B\ndatePublished: 2020-05-05\n---\n",
+            )],
+            JAN_1_2024 + DAY,
+            "Add b",
+        );
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: A\n---\nv2")],
+            JAN_1_2024 + 3 * DAY,
+            "Update a",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        let a = git.find_post_by_slug(&slug("a")).unwrap().unwrap();
+        let b = git.find_post_by_slug(&slug("b")).unwrap().unwrap();
+
+        assert_eq!(a.publish_date.unwrap().to_string(), "2024-01-01");
+        assert_eq!(a.modified.unwrap().timestamp(), JAN_1_2024 + 3 * DAY);
+        assert_eq!(b.publish_date.unwrap().to_string(), "2020-05-05");
+        assert_eq!(b.modified.unwrap().timestamp(), JAN_1_2024 + DAY);
+    }
+
+    #[test]
+    fn front_matter_dates_win_over_history() {
+        let (dir, repo) = git_repo();
+        commit_files(
+            &repo,
+            &[(
+                "posts/a.md",
+                "---\ntitle: A\ndateModified: 2023-06-01\nupdated: 2023-07-01T10:00:00+02:00\n---\n",
+            )],
+            JAN_1_2024,
+            "Add a",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        let a = git.find_post_by_slug(&slug("a")).unwrap().unwrap();
+
+        assert_eq!(a.publish_date.unwrap().to_string(), "2024-01-01");
+        assert_eq!(
+            a.modified.unwrap().to_rfc3339(),
+            "2023-06-01T00:00:00+00:00"
+        );
+        assert_eq!(a.updated.unwrap().to_rfc3339(), "2023-07-01T08:00:00+00:00");
+    }
+
+    #[test]
+    fn serves_content_of_selected_ref() {
+        let (dir, repo) = git_repo();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: Old\n---\n")],
+            JAN_1_2024,
+            "v1",
+        );
+        let first = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("published", &first, false).unwrap();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: New\n---\n")],
+            JAN_1_2024 + DAY,
+            "v2",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "published").unwrap();
+        assert_eq!(
+            git.get_all_posts().unwrap()[0].title.as_deref(),
+            Some("Old")
+        );
+
+        git.set_reference("HEAD").unwrap();
+        assert_eq!(
+            git.get_all_posts().unwrap()[0].title.as_deref(),
+            Some("New")
+        );
+    }
+
+    #[test]
+    fn lists_revisions_of_a_post_newest_first() {
+        let (dir, repo) = git_repo();
+        commit_files(&repo, &[("posts/a.md", "v1\n")], JAN_1_2024, "Write a");
+        commit_files(&repo, &[("posts/b.md", "")], JAN_1_2024 + DAY, "Write b");
+        commit_files(
+            &repo,
+            &[("posts/a.md", "v2\n")],
+            JAN_1_2024 + 2 * DAY,
+            "Fix typo in a",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        let history = git.post_history(&slug("a")).unwrap().unwrap();
+
+        let messages: Vec<&str> = history.iter().map(|r| r.message.as_str()).collect();
+        assert_eq!(messages, vec!["Fix typo in a", "Write a"]);
+        assert_eq!(history[0].date.timestamp(), JAN_1_2024 + 2 * DAY);
+        assert!(git.post_history(&slug("missing")).unwrap().is_none());
+    }
+
+    #[test]
+    fn merges_only_count_for_files_that_differ_from_every_parent() {
+        let (dir, repo) = git_repo();
+        let head = || repo.head().unwrap().peel_to_commit().unwrap();
+        commit_files(&repo, &[("posts/a.md", "v1\n")], JAN_1_2024, "Write a");
+        let first = head();
+        commit_files(&repo, &[("posts/b.md", "b\n")], JAN_1_2024 + DAY, "Write b");
+        let side = head();
+        repo.reset(first.as_object(), git2::ResetType::Hard, None)
+            .unwrap();
+        commit_files(&repo, &[("posts/a.md", "v2\n")], JAN_1_2024 + DAY, "Fix a");
+        let main = head();
+
+        std::fs::write(dir.path().join("posts/b.md"), "b\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("posts/b.md")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::new(
+            "Author",
+            "author@example.com",
+            &git2::Time::new(JAN_1_2024 + 2 * DAY, 0),
+        )
+        .unwrap();
+        // The side branch is the first parent, so HEAD cannot be updated
+        // by the commit itself.
+        let merge = repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "Merge side",
+                &tree,
+                &[&side, &main],
+            )
+            .unwrap();
+        repo.set_head_detached(merge).unwrap();
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        let messages = |name: &str| -> Vec<String> {
+            let history = git.post_history(&slug(name)).unwrap().unwrap();
+            history.into_iter().map(|r| r.message).collect()
+        };
+
+        assert_eq!(messages("a"), vec!["Fix a", "Write a"]);
+        assert_eq!(messages("b"), vec!["Write b"]);
+    }
+
+    #[test]
+    fn diffs_a_post_between_revisions() {
+        let (dir, repo) = git_repo();
+        commit_files(&repo, &[("posts/a.md", "first line\n")], JAN_1_2024, "v1");
+        commit_files(
+            &repo,
+            &[
+                ("posts/a.md", "first line\nsecond line\n"),
+                ("posts/b.md", "b\n"),
+            ],
+            JAN_1_2024 + DAY,
+            "v2",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        let history = git.post_history(&slug("a")).unwrap().unwrap();
+        let diff = git
+            .post_diff(&slug("a"), &history[1].hash, &history[0].hash)
+            .unwrap()
+            .unwrap();
+
+        assert!(diff.contains("+second line"));
+        assert!(!diff.contains("posts/b.md"));
+        assert!(
+            git.post_diff(&slug("a"), "nope", &history[0].hash)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn diff_rejects_refs_outside_the_post_history() {
+        let (dir, repo) = git_repo();
+        commit_files(&repo, &[("posts/a.md", "published\n")], JAN_1_2024, "v1");
+        let published = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("published", &published, false).unwrap();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "secret draft\n")],
+            JAN_1_2024 + DAY,
+            "Draft",
+        );
+        let draft = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("drafts", &draft, false).unwrap();
+        let draft = draft.id().to_string();
+
+        let git = GitBlogRepository::open(dir.path(), "published").unwrap();
+        let first = published.id().to_string();
+
+        for other in ["drafts", "HEAD", draft.as_str(), &first[..7]] {
+            assert!(
+                git.post_diff(&slug("a"), &first, other).unwrap().is_none(),
+                "{other}"
+            );
+        }
+        assert!(git.post_diff(&slug("a"), &first, &first).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn switching_ref_invalidates_cached_html() {
+        use crate::BlogDir;
+        use crate::config::BlogConfig;
+        use crate::renderer::{BlogPostHandler, Renderer};
+
+        let (dir, repo) = git_repo();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: Old\n---\n")],
+            JAN_1_2024,
+            "v1",
+        );
+        let first = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("published", &first, false).unwrap();
+        commit_files(
+            &repo,
+            &[("posts/a.md", "---\ntitle: New\n---\n")],
+            JAN_1_2024 + DAY,
+            "v2",
+        );
+
+        let git = GitBlogRepository::open(dir.path(), "published").unwrap();
+        let config = BlogConfig::default();
+        let handler = BlogPostHandler::new(config.clone(), git.clone(), &BlogDir(".".into()));
+        let cache = CachedRenderer::new(Arc::new(handler), config.compression, config.cache);
+        let content = GitContent::new(git, cache.clone());
+
+        assert!(
+            cache
+                .post_for(slug("a"))
+                .await
+                .unwrap()
+                .html
+                .0
+                .contains("Old")
+        );
+        content.switch_ref("HEAD").await.unwrap();
+
+        assert!(
+            cache
+                .post_for(slug("a"))
+                .await
+                .unwrap()
+                .html
+                .0
+                .contains("New")
+        );
+        assert_eq!(content.reference(), "HEAD");
+    }
+
+    #[test]
+    fn rejects_unknown_refs() {
+        let (dir, repo) = git_repo();
+        commit_files(&repo, &[("posts/a.md", "")], JAN_1_2024, "v1");
+
+        assert!(GitBlogRepository::open(dir.path(), "no-such-branch").is_err());
+
+        let git = GitBlogRepository::open(dir.path(), "HEAD").unwrap();
+        assert!(git.set_reference("no-such-branch").is_err());
+        assert_eq!(git.reference(), "HEAD");
+    }
+}
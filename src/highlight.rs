@@ -0,0 +1,689 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Cursor;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use sha2::{Digest, Sha256};
+use syntect::dumps;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    ClassStyle, ClassedHTMLGenerator, IncludeBackground, css_for_theme_with_class_style,
+    styled_line_to_highlighted_html,
+};
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::config::{HighlightMode, HighlightingConfig};
+use crate::diagram;
+
+// Outside `/static`, so it cannot shadow a stylesheet of the site.
+pub(crate) const STYLESHEET_PATH: &str = "/_highlight/theme.css";
+pub(crate) const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+// What the info string of a fenced code block asks for, as in
+// ```` ```rust title="main.rs" {3,5-7} linenos ````. Unknown words are
+// ignored.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CodeBlockInfo {
+    pub lang: Option<String>,
+    pub title: Option<String>,
+    pub line_numbers: bool,
+    pub highlighted_lines: Vec<RangeInclusive<usize>>,
+}
+
+// Prefixed, so the classes of the highlighted code cannot clash with the
+// ones of the site's stylesheet.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+// syntect's bundled syntaxes and themes are dumps precompiled when syntect
+// is built. They are loaded on first use and shared by every highlighter,
+// together with the regexes the syntaxes compile lazily while highlighting.
+static DEFAULT_SYNTAXES: LazyLock<Arc<SyntaxSet>> =
+    LazyLock::new(|| Arc::new(SyntaxSet::load_defaults_newlines()));
+static DEFAULT_THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+// Turns code blocks into highlighted html, with colors inline or as classes
+// styled by a generated stylesheet.
+pub(crate) struct Highlighter {
+    syntax_set: Arc<SyntaxSet>,
+    aliases: BTreeMap<String, String>,
+    mode: HighlightMode,
+    theme: Theme,
+    stylesheet: Option<String>,
+}
+
+// A `.sublime-syntax` file by its name and content.
+pub(crate) type SyntaxFile = (String, String);
+
+impl Highlighter {
+    // Custom `.tmTheme` files, the syntaxes directory and a relative cache
+    // directory are relative to the blog directory.
+    pub fn new(config: &HighlightingConfig, blog_dir: &Path) -> Self {
+        let syntaxes = syntax_files(&blog_dir.join(&config.syntaxes_dir));
+        let cache_dir = cache_dir(config, blog_dir);
+        Self::with_files(config, &syntaxes, cache_dir.as_deref(), |path| {
+            std::fs::read(blog_dir.join(path)).ok()
+        })
+    }
+
+    // `read` returns the content of a `.tmTheme` file by its configured path.
+    // Without a cache directory the syntax set is built on every start.
+    pub fn with_files(
+        config: &HighlightingConfig,
+        syntaxes: &[SyntaxFile],
+        cache_dir: Option<&Path>,
+        read: impl Fn(&str) -> Option<Vec<u8>>,
+    ) -> Self {
+        let themes = &*DEFAULT_THEMES;
+        let load = |name: &str| {
+            load_theme(themes, name, &read)
+                .inspect_err(|e| eprintln!("Warning: {e}"))
+                .ok()
+        };
+        let theme = load(&config.theme).unwrap_or_else(|| themes.themes[DEFAULT_THEME].clone());
+        let stylesheet = match config.mode {
+            HighlightMode::Inline => None,
+            HighlightMode::Classes => {
+                let dark_theme = config.dark_theme.as_deref().and_then(load);
+                Some(stylesheet(&theme, dark_theme.as_ref()))
+            }
+        };
+
+        let syntax_set = match syntaxes.is_empty() {
+            true => DEFAULT_SYNTAXES.clone(),
+            false => Arc::new(syntax_set(&DEFAULT_SYNTAXES, syntaxes, cache_dir)),
+        };
+        Highlighter {
+            syntax_set,
+            aliases: config.aliases.clone(),
+            mode: config.mode,
+            theme,
+            stylesheet,
+        }
+    }
+
+    // A `<pre><code>` block, inside a `<figure>` captioned with the title
+    // when there is one. With line numbers or highlighted lines every line
+    // is wrapped in a `span.line`.
+    pub fn code_block(&self, code: &str, info: &CodeBlockInfo) -> String {
+        let syntax = info
+            .lang
+            .as_deref()
+            .and_then(|lang| self.find_syntax(lang))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let highlighted = match self.mode {
+            HighlightMode::Inline => self.inline_html(code, syntax),
+            HighlightMode::Classes => self.classed_html(code, syntax),
+        }
+        .unwrap_or_else(|_| escape(code));
+
+        let mut html = String::new();
+        if let Some(title) = &info.title {
+            html.push_str("<figure class=\"code-block\"><figcaption class=\"code-title\">");
+            html.push_str(&escape(title));
+            html.push_str("</figcaption>");
+        }
+        html.push_str(&format!("<pre{}", self.pre_attributes()));
+        match &info.lang {
+            Some(lang) => {
+                let lang = escape(lang);
+                html.push_str(&format!(
+                    " data-lang=\"{lang}\"><code class=\"language-{lang}\">"
+                ));
+            }
+            None => html.push_str("><code>"),
+        }
+        if info.line_numbers || !info.highlighted_lines.is_empty() {
+            for (index, line) in split_lines(&highlighted).iter().enumerate() {
+                let number = index + 1;
+                let class = if info.is_highlighted(number) {
+                    "line highlighted"
+                } else {
+                    "line"
+                };
+                html.push_str(&format!("<span class=\"{class}\">"));
+                if info.line_numbers {
+                    html.push_str(&format!(
+                        "<span class=\"line-number\" aria-hidden=\"true\">{number}</span>"
+                    ));
+                }
+                html.push_str(line);
+                html.push_str("</span>\n");
+            }
+        } else {
+            html.push_str(&highlighted);
+        }
+        html.push_str("</code></pre>");
+        if info.title.is_some() {
+            html.push_str("</figure>");
+        }
+        html
+    }
+
+    // Aliases name a syntax or a token of one, like a file extension.
+    fn find_syntax(&self, lang: &str) -> Option<&SyntaxReference> {
+        let lang = self.aliases.get(lang).map_or(lang, String::as_str);
+        self.syntax_set
+            .find_syntax_by_token(lang)
+            .or_else(|| self.syntax_set.find_syntax_by_name(lang))
+    }
+
+    // Languages of fenced code blocks that are shown as plain text for lack
+    // of a syntax, each once.
+    pub fn unknown_languages(&self, markdown: &str) -> Vec<String> {
+        let languages: BTreeSet<String> = Parser::new(markdown)
+            .filter_map(|event| match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                    CodeBlockInfo::parse(&info).lang
+                }
+                _ => None,
+            })
+            .filter(|lang| {
+                self.find_syntax(lang).is_none() && !diagram::LANGUAGES.contains(&lang.as_str())
+            })
+            .collect();
+        languages.into_iter().collect()
+    }
+
+    // Inline colors need the theme's background on the block itself.
+    fn pre_attributes(&self) -> String {
+        match self.mode {
+            HighlightMode::Inline => {
+                let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+                format!(
+                    " style=\"background-color:#{:02x}{:02x}{:02x};\"",
+                    background.r, background.g, background.b
+                )
+            }
+            HighlightMode::Classes => " class=\"hl-code\"".to_string(),
+        }
+    }
+
+    fn inline_html(&self, code: &str, syntax: &SyntaxReference) -> Result<String, syntect::Error> {
+        let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        let mut html = String::new();
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter.highlight_line(line, &self.syntax_set)?;
+            html.push_str(&styled_line_to_highlighted_html(
+                &regions,
+                IncludeBackground::IfDifferent(background),
+            )?);
+        }
+        Ok(html)
+    }
+
+    fn classed_html(&self, code: &str, syntax: &SyntaxReference) -> Result<String, syntect::Error> {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntax_set, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line)?;
+        }
+        Ok(generator.finalize())
+    }
+
+    // Only in class mode, inline colors need no stylesheet.
+    pub fn stylesheet(&self) -> Option<&str> {
+        self.stylesheet.as_deref()
+    }
+}
+
+impl CodeBlockInfo {
+    pub fn parse(info: &str) -> Self {
+        let mut parsed = CodeBlockInfo::default();
+        for (position, word) in words(info).into_iter().enumerate() {
+            if let Some(title) = word.strip_prefix("title=") {
+                parsed.title = Some(title.trim_matches('"').to_string());
+            } else if word == "linenos" {
+                parsed.line_numbers = true;
+            } else if let Some(ranges) = word.strip_prefix('{').and_then(|w| w.strip_suffix('}')) {
+                parsed.highlighted_lines = ranges.split(',').filter_map(line_range).collect();
+            } else if position == 0 && !word.contains('=') {
+                parsed.lang = Some(word);
+            }
+        }
+        parsed
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted_lines
+            .iter()
+            .any(|range| range.contains(&line))
+    }
+}
+
+// Splits at whitespace outside of double quotes.
+fn words(info: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in info.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// `5` or `5-7`.
+fn line_range(range: &str) -> Option<RangeInclusive<usize>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+}
+
+// Highlighted html split into lines. Spans that continue over a line break,
+// like those of a block comment, are closed at the end of the line and
+// opened again on the next, so every line can be wrapped on its own.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("</span>") {
+            open.pop();
+            line.push_str("</span>");
+            rest = &rest["</span>".len()..];
+        } else if rest.starts_with("<span") {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            open.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c == '\n' {
+            line.push_str(&"</span>".repeat(open.len()));
+            lines.push(std::mem::replace(&mut line, open.concat()));
+            has_text = false;
+            rest = &rest[1..];
+        } else {
+            line.push(c);
+            has_text = true;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if has_text {
+        lines.push(line);
+    }
+    lines
+}
+
+// The configured cache directory, relative to the blog directory, or the
+// user's cache directory. A shared temporary directory is never used, as
+// other users could plant a dump there.
+pub(crate) fn cache_dir(config: &HighlightingConfig, blog_dir: &Path) -> Option<PathBuf> {
+    if let Some(dir) = &config.cache_dir {
+        return Some(blog_dir.join(dir));
+    }
+    let user_cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(user_cache.join("blog-engine"))
+}
+
+// Building a syntax set with extra definitions links all syntaxes again,
+// which is slow, so the result is kept in `cache_dir` under a hash of the
+// definitions. Failing to use the cache only costs time.
+fn syntax_set(base: &SyntaxSet, syntaxes: &[SyntaxFile], cache_dir: Option<&Path>) -> SyntaxSet {
+    let cache_file = cache_dir.map(|dir| cache_file(base, syntaxes, dir));
+    if let Some(syntax_set) = cache_file
+        .as_ref()
+        .and_then(|file| dumps::from_uncompressed_dump_file(file).ok())
+    {
+        return syntax_set;
+    }
+
+    let mut builder = base.clone().into_builder();
+    for (name, source) in syntaxes {
+        let fallback_name = name.trim_end_matches(".sublime-syntax");
+        match SyntaxDefinition::load_from_str(source, true, Some(fallback_name)) {
+            Ok(syntax) => builder.add(syntax),
+            Err(e) => eprintln!("Warning: syntax {name} could not be loaded: {e}"),
+        }
+    }
+    let syntax_set = builder.build();
+    if let Some(cache_file) = cache_file
+        && let Err(e) = write_cache(&syntax_set, &cache_file)
+    {
+        eprintln!(
+            "Warning: syntax cache {} could not be written: {e}",
+            cache_file.display()
+        );
+    }
+    syntax_set
+}
+
+// Written next to the final file and renamed, so a concurrent start never
+// reads a partial dump. A new directory is only accessible to its owner.
+fn write_cache(syntax_set: &SyntaxSet, cache_file: &Path) -> Result<(), Box<dyn Error>> {
+    let dir = cache_file.parent().ok_or("cache file has no directory")?;
+    if !dir.is_dir() {
+        std::fs::create_dir_all(dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    let partial = cache_file.with_extension(format!("{}.partial", std::process::id()));
+    dumps::dump_to_uncompressed_file(syntax_set, &partial)?;
+    std::fs::rename(&partial, cache_file).inspect_err(|_| {
+        let _ = std::fs::remove_file(&partial);
+    })?;
+    Ok(())
+}
+
+// The syntect release whose dump format the cache is written in; keep it in
+// step with Cargo.toml.
+const SYNTECT_VERSION: &str = "5.2";
+
+// SHA-256 over everything the dump depends on; unlike `DefaultHasher` it is
+// stable across Rust releases. The base set is described by its syntaxes,
+// serializing it whole would cost more than the cache saves.
+fn cache_file(base: &SyntaxSet, syntaxes: &[SyntaxFile], cache_dir: &Path) -> PathBuf {
+    let mut hasher = Sha256::new();
+    let mut update = |part: &str| {
+        hasher.update(part.len().to_le_bytes());
+        hasher.update(part);
+    };
+    update(env!("CARGO_PKG_VERSION"));
+    update(SYNTECT_VERSION);
+    for syntax in base.syntaxes() {
+        update(&syntax.name);
+        update(&syntax.scope.build_string());
+        update(&syntax.file_extensions.join(","));
+        update(syntax.first_line_match.as_deref().unwrap_or_default());
+    }
+    for (name, source) in syntaxes {
+        update(name);
+        update(source);
+    }
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    cache_dir.join(format!("blog-engine-syntaxes-{hex}.packdump"))
+}
+
+// The `.sublime-syntax` files directly in `dir`, sorted by name so the
+// cache key does not depend on the directory order.
+fn syntax_files(dir: &Path) -> Vec<SyntaxFile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<SyntaxFile> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sublime-syntax"))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            let source = std::fs::read_to_string(&path)
+                .inspect_err(|e| eprintln!("Warning: {} could not be read: {e}", path.display()))
+                .ok()?;
+            Some((name, source))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// A theme built into syntect by name, or a `.tmTheme` file by path.
+fn load_theme(
+    themes: &ThemeSet,
+    name: &str,
+    read: &impl Fn(&str) -> Option<Vec<u8>>,
+) -> Result<Theme, String> {
+    if !name.ends_with(".tmTheme") {
+        return themes.themes.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = themes.themes.keys().map(String::as_str).collect();
+            format!(
+                "Unknown highlighting theme '{}', expected one of {} or a .tmTheme file",
+                name,
+                known.join(", ")
+            )
+        });
+    }
+    let content = read(name).ok_or_else(|| format!("Highlighting theme {name} not found"))?;
+    ThemeSet::load_from_reader(&mut Cursor::new(content))
+        .map_err(|e| format!("Highlighting theme {name} could not be loaded: {e}"))
+}
+
+// The dark theme applies when the visitor's system prefers a dark color
+// scheme.
+fn stylesheet(theme: &Theme, dark_theme: Option<&Theme>) -> String {
+    let css = |theme| css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap_or_default();
+    let mut stylesheet = css(theme);
+    if let Some(dark_theme) = dark_theme {
+        stylesheet.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        stylesheet.push_str(&css(dark_theme));
+        stylesheet.push_str("}\n");
+    }
+    stylesheet
+}
+
+fn escape(code: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark::escape::escape_html(&mut escaped, code).unwrap_or_default();
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntect::parsing::SyntaxSetBuilder;
+
+    fn config(mode: HighlightMode, theme: &str, dark_theme: Option<&str>) -> HighlightingConfig {
+        HighlightingConfig {
+            mode,
+            theme: theme.to_string(),
+            dark_theme: dark_theme.map(str::to_string),
+            ..HighlightingConfig::default()
+        }
+    }
+
+    #[test]
+    fn class_mode_emits_classes_and_a_stylesheet_for_both_schemes() {
+        let highlighter = Highlighter::with_files(
+            &config(
+                HighlightMode::Classes,
+                "InspiredGitHub",
+                Some("base16-ocean.dark"),
+            ),
+            &[],
+            None,
+            |_| None,
+        );
+
+        let html = highlighter.code_block("fn main() {}\n", &CodeBlockInfo::parse("rust"));
+        let stylesheet = highlighter.stylesheet().unwrap();
+
+        assert!(
+            html.starts_with(
+                "<pre class=\"hl-code\" data-lang=\"rust\"><code class=\"language-rust\">"
+            ),
+            "{html}"
+        );
+        assert!(html.contains("class=\"hl-"), "{html}");
+        assert!(!html.contains("style="), "{html}");
+        assert!(stylesheet.contains("theme \"GitHub\""), "{stylesheet}");
+        assert!(stylesheet.contains("@media (prefers-color-scheme: dark)"));
+        assert!(stylesheet.contains(".hl-code {"));
+    }
+
+    #[test]
+    fn inline_mode_has_no_stylesheet() {
+        let highlighter = Highlighter::with_files(
+            &config(HighlightMode::Inline, DEFAULT_THEME, None),
+            &[],
+            None,
+            |_| None,
+        );
+
+        let html = highlighter.code_block("x", &CodeBlockInfo::default());
+        assert!(html.starts_with("<pre style=\"background-color:#2b303b;\"><code>"));
+        assert!(highlighter.stylesheet().is_none());
+    }
+
+    #[test]
+    fn loads_tm_theme_files_and_falls_back_on_unknown_themes() {
+        let tm_theme = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>name</key><string>Custom</string>
+<key>settings</key><array><dict><key>settings</key><dict>
+<key>background</key><string>#123456</string>
+</dict></dict></array>
+</dict></plist>"#;
+        let read = |path: &str| (path == "themes/custom.tmTheme").then(|| tm_theme.into());
+
+        let custom = Highlighter::with_files(
+            &config(HighlightMode::Classes, "themes/custom.tmTheme", None),
+            &[],
+            None,
+            read,
+        );
+        let unknown = Highlighter::with_files(
+            &config(HighlightMode::Classes, "no-such-theme", None),
+            &[],
+            None,
+            read,
+        );
+
+        assert!(custom.stylesheet().unwrap().contains("#123456"));
+        assert!(
+            unknown
+                .stylesheet()
+                .unwrap()
+                .contains("theme \"Base16 Ocean Dark\"")
+        );
+    }
+
+    #[test]
+    fn parses_info_strings() {
+        assert_eq!(
+            CodeBlockInfo::parse(r#"rust title="src/main file.rs" {3,5-7} linenos"#),
+            CodeBlockInfo {
+                lang: Some("rust".to_string()),
+                title: Some("src/main file.rs".to_string()),
+                line_numbers: true,
+                highlighted_lines: vec![3..=3, 5..=7],
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("linenos"),
+            CodeBlockInfo {
+                line_numbers: true,
+                ..CodeBlockInfo::default()
+            }
+        );
+        assert_eq!(CodeBlockInfo::parse("{x,2}").highlighted_lines, vec![2..=2]);
+    }
+
+    #[test]
+    fn wraps_lines_with_numbers_highlights_and_title() {
+        let highlighter = Highlighter::with_files(
+            &config(HighlightMode::Classes, DEFAULT_THEME, None),
+            &[],
+            None,
+            |_| None,
+        );
+
+        let html = highlighter.code_block(
+            "/* one\ntwo */\nthree\n",
+            &CodeBlockInfo::parse(r#"rust title="a<b.rs" {2} linenos"#),
+        );
+
+        assert!(html.starts_with(
+            "<figure class=\"code-block\"><figcaption class=\"code-title\">a&lt;b.rs</figcaption><pre"
+        ));
+        assert!(html.ends_with("</code></pre></figure>"));
+        assert_eq!(html.matches("<span class=\"line\">").count(), 2);
+        assert_eq!(html.matches("<span class=\"line highlighted\">").count(), 1);
+        assert!(html.contains("aria-hidden=\"true\">3</span>"));
+        // The comment spans two lines; both lines are balanced on their own.
+        for line in split_lines(&html) {
+            assert_eq!(
+                line.matches("<span").count(),
+                line.matches("</span>").count()
+            );
+        }
+    }
+
+    #[test]
+    fn cache_dir_is_relative_to_the_blog_and_never_the_temp_dir() {
+        let config = HighlightingConfig {
+            cache_dir: Some(PathBuf::from(".cache")),
+            ..HighlightingConfig::default()
+        };
+        assert_eq!(
+            cache_dir(&config, Path::new("/blog")),
+            Some(PathBuf::from("/blog/.cache"))
+        );
+        let default = cache_dir(&HighlightingConfig::default(), Path::new("/blog"));
+        assert!(default.is_none_or(|dir| !dir.starts_with(std::env::temp_dir())));
+    }
+
+    // Linking the bundled syntaxes takes most of a minute without
+    // optimisations, so the set is built on top of plain text only. The first
+    // start builds and writes the cache, the next ones read it.
+    #[test]
+    fn custom_syntaxes_are_built_once_and_aliases_resolve() {
+        let terraform = "%YAML 1.2\n---\nname: Terraform\nfile_extensions: [tf]\nscope: source.terraform\ncontexts:\n  main:\n    - match: '\\bresource\\b'\n      scope: keyword.terraform\n";
+        let syntaxes = [(
+            "terraform.sublime-syntax".to_string(),
+            terraform.to_string(),
+        )];
+        let blog_dir = tempfile::TempDir::new().unwrap();
+        let mut base = SyntaxSetBuilder::new();
+        base.add_plain_text_syntax();
+        let config = HighlightingConfig {
+            mode: HighlightMode::Classes,
+            aliases: BTreeMap::from([
+                ("hcl".to_string(), "Terraform".to_string()),
+                ("terraform".to_string(), "tf".to_string()),
+            ]),
+            cache_dir: Some(PathBuf::from("cache")),
+            ..HighlightingConfig::default()
+        };
+        let cache_dir = cache_dir(&config, blog_dir.path()).unwrap();
+
+        let base = base.build();
+        let built = syntax_set(&base, &syntaxes, Some(&cache_dir));
+        let files: Vec<_> = std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        // The small set stored under the key of the default syntaxes shows
+        // the highlighter reads its set from the cache.
+        let default_key = cache_file(&DEFAULT_SYNTAXES, &syntaxes, &cache_dir);
+        dumps::dump_to_uncompressed_file(&built, &default_key).unwrap();
+        let highlighter = Highlighter::with_files(&config, &syntaxes, Some(&cache_dir), |_| None);
+
+        assert_eq!(files, vec![cache_file(&base, &syntaxes, &cache_dir)]);
+        assert_ne!(files[0], default_key);
+        assert_eq!(built.syntaxes().len(), 2);
+        assert_eq!(
+            highlighter
+                .unknown_languages("```hcl\n```\n```tf\n```\n```terraform\n```\n```rust\n```\n"),
+            vec!["rust"]
+        );
+        let html = highlighter.code_block("resource\n", &CodeBlockInfo::parse("hcl"));
+        assert!(html.contains("hl-keyword hl-terraform"), "{html}");
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::Markdown;
+use crate::blog_repository::{
+    BlogRepository, FileSystemBlogRepository, RepositoryError, SourceEntry, sort_posts,
+};
+use crate::slug::Slug;
+
+// Below this many changed files parsing on the current thread is faster.
+const PARALLEL_LOAD_THRESHOLD: usize = 64;
+
+// Lookups within this long of the previous scan do not touch the disk.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Keeps parsed posts in memory. The posts directory is listed again at most
+// once per rescan interval and only files whose modification time changed
+// are re-read.
+pub(crate) struct IndexedBlogRepository {
+    source: FileSystemBlogRepository,
+    rescan_interval: Duration,
+    index: RwLock<PostIndex>,
+}
+
+#[derive(Default)]
+struct PostIndex {
+    files: HashMap<PathBuf, IndexedPost>,
+    sorted: Arc<[Markdown]>,
+    by_slug: HashMap<String, usize>,
+    scanned: Option<Instant>,
+}
+
+struct IndexedPost {
+    modified: Option<chrono::DateTime<chrono::Utc>>,
+    markdown: Markdown,
+}
+
+impl IndexedBlogRepository {
+    pub fn new(source: FileSystemBlogRepository) -> Self {
+        Self::with_rescan_interval(source, RESCAN_INTERVAL)
+    }
+
+    fn with_rescan_interval(source: FileSystemBlogRepository, rescan_interval: Duration) -> Self {
+        let repo = IndexedBlogRepository {
+            source,
+            rescan_interval,
+            index: RwLock::new(PostIndex::default()),
+        };
+        if let Err(e) = repo.refresh() {
+            eprintln!("Warning: Could not build post index: {:?}", e);
+        }
+        repo
+    }
+
+    fn refresh(&self) -> Result<(), RepositoryError> {
+        let scanned = self.read_index()?.scanned;
+        if scanned.is_some_and(|scanned| scanned.elapsed() < self.rescan_interval) {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let entries = self.source.post_entries()?;
+        let (changed, removed) = self.read_index()?.changes(&entries);
+        let loaded = if changed.is_empty() {
+            Vec::new()
+        } else {
+            load_in_parallel(&self.source, changed)?
+        };
+
+        let mut index = self
+            .index
+            .write()
+            .map_err(|_| RepositoryError::UnexpectedError)?;
+        if !loaded.is_empty() || !removed.is_empty() {
+            index.apply(loaded, removed);
+        }
+        index.scanned = Some(started);
+        Ok(())
+    }
+
+    fn read_index(&self) -> Result<std::sync::RwLockReadGuard<'_, PostIndex>, RepositoryError> {
+        self.index
+            .read()
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+}
+
+impl PostIndex {
+    fn changes<'a>(&self, entries: &'a [SourceEntry]) -> (Vec<&'a SourceEntry>, Vec<PathBuf>) {
+        let changed = entries
+            .iter()
+            .filter(|entry| {
+                self.files
+                    .get(&entry.path)
+                    .is_none_or(|post| post.modified != entry.modified)
+            })
+            .collect();
+        let listed: HashSet<&PathBuf> = entries.iter().map(|entry| &entry.path).collect();
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !listed.contains(path))
+            .cloned()
+            .collect();
+        (changed, removed)
+    }
+
+    fn apply(&mut self, loaded: Vec<(PathBuf, IndexedPost)>, removed: Vec<PathBuf>) {
+        for path in removed {
+            self.files.remove(&path);
+        }
+        self.files.extend(loaded);
+
+        let mut sorted: Vec<Markdown> = self
+            .files
+            .values()
+            .map(|post| post.markdown.clone())
+            .collect();
+        sort_posts(&mut sorted);
+        self.sorted = sorted.into();
+
+        self.by_slug = HashMap::new();
+        for (position, markdown) in self.sorted.iter().enumerate() {
+            for slug in &markdown.slugs {
+                self.by_slug.entry(slug.clone()).or_insert(position);
+            }
+        }
+    }
+}
+
+fn load_in_parallel(
+    source: &FileSystemBlogRepository,
+    entries: Vec<&SourceEntry>,
+) -> Result<Vec<(PathBuf, IndexedPost)>, RepositoryError> {
+    let load = |entry: &SourceEntry| {
+        source.load_post(entry).map(|markdown| {
+            let post = IndexedPost {
+                modified: entry.modified,
+                markdown,
+            };
+            (entry.path.clone(), post)
+        })
+    };
+
+    if entries.len() < PARALLEL_LOAD_THRESHOLD {
+        return entries.into_iter().map(load).collect();
+    }
+
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk_size = entries.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|entry| load(entry))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+            })
+            .collect();
+
+        let mut loaded = Vec::with_capacity(entries.len());
+        for worker in workers {
+            let posts = worker
+                .join()
+                .map_err(|_| RepositoryError::UnexpectedError)??;
+            loaded.extend(posts);
+        }
+        Ok(loaded)
+    })
+}
+
+impl BlogRepository for IndexedBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        self.refresh()?;
+        Ok(Arc::clone(&self.read_index()?.sorted))
+    }
+
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        self.refresh()?;
+        let index = self.read_index()?;
+        Ok(index
+            .by_slug
+            .get(slug.as_str())
+            .map(|&position| index.sorted[position].clone()))
+    }
+
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        self.source.get_page(slug)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slug::slug;
+    use std::fs;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    fn repo_with(posts: &[(&str, &str)]) -> (TempDir, IndexedBlogRepository) {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("posts")).unwrap();
+        for (name, content) in posts {
+            write_post(&dir, name, content);
+        }
+        let source = FileSystemBlogRepository::new(dir.path().into());
+        let repo = IndexedBlogRepository::with_rescan_interval(source, Duration::ZERO);
+        (dir, repo)
+    }
+
+    fn write_post(dir: &TempDir, name: &str, content: &str) {
+        fs::write(dir.path().join("posts").join(name), content).unwrap();
+    }
+
+    fn titles(repo: &IndexedBlogRepository) -> Vec<String> {
+        repo.get_all_posts()
+            .unwrap()
+            .iter()
+            .map(|post| post.title.clone().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn finds_posts_by_front_matter_slug_and_filename() {
+        let (_dir, repo) = repo_with(&[("file-name.md", "---\ntitle: A\nslug: a\n---\n")]);
+
+        assert!(repo.find_post_by_slug(&slug("a")).unwrap().is_some());
+        assert!(
+            repo.find_post_by_slug(&slug("file-name"))
+                .unwrap()
+                .is_some()
+        );
+        assert!(repo.find_post_by_slug(&slug("other")).unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_posts_sorted() {
+        let (_dir, repo) = repo_with(&[
+            (
+                "old.md",
+                "---\ntitle: Old\ndatePublished: 2020-01-01\n---\n",
+            ),
+            ("undated.md", "---\ntitle: Undated\n---\n"),
+            (
+                "new.md",
+                "---\ntitle: New\ndatePublished: 2022-01-01\n---\n",
+            ),
+        ]);
+
+        assert_eq!(titles(&repo), vec!["New", "Old", "Undated"]);
+    }
+
+    #[test]
+    fn picks_up_added_changed_and_removed_files() {
+        let (dir, repo) = repo_with(&[
+            ("changed.md", "---\ntitle: Before\n---\n"),
+            ("removed.md", "---\ntitle: Removed\n---\n"),
+        ]);
+        assert_eq!(titles(&repo), vec!["Before", "Removed"]);
+
+        write_post(&dir, "added.md", "---\ntitle: Added\n---\n");
+        write_post(&dir, "changed.md", "---\ntitle: After\n---\n");
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("posts/changed.md"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        fs::remove_file(dir.path().join("posts/removed.md")).unwrap();
+
+        assert_eq!(titles(&repo), vec!["Added", "After"]);
+        assert!(repo.find_post_by_slug(&slug("removed")).unwrap().is_none());
+    }
+
+    #[test]
+    fn shares_posts_and_skips_the_disk_until_the_rescan_interval_passed() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("posts")).unwrap();
+        write_post(&dir, "first.md", "---\ntitle: First\n---\n");
+        let source = FileSystemBlogRepository::new(dir.path().into());
+        let repo = IndexedBlogRepository::with_rescan_interval(source, Duration::from_secs(3600));
+
+        let before = repo.get_all_posts().unwrap();
+        write_post(&dir, "second.md", "---\ntitle: Second\n---\n");
+        let after = repo.get_all_posts().unwrap();
+
+        assert!(Arc::ptr_eq(&before, &after));
+        assert!(repo.find_post_by_slug(&slug("second")).unwrap().is_none());
+
+        repo.index.write().unwrap().scanned = None;
+        assert_eq!(titles(&repo), vec!["First", "Second"]);
+    }
+
+    #[test]
+    fn loads_large_directories_in_parallel() {
+        let posts: Vec<(String, String)> = (0..PARALLEL_LOAD_THRESHOLD * 2)
+            .map(|i| {
+                (
+                    format!("post-{i}.md"),
+                    format!("---\ntitle: Post {i}\n---\n"),
+                )
+            })
+            .collect();
+        let posts: Vec<(&str, &str)> = posts
+            .iter()
+            .map(|(name, content)| (name.as_str(), content.as_str()))
+            .collect();
+
+        let (_dir, repo) = repo_with(&posts);
+
+        assert_eq!(
+            repo.get_all_posts().unwrap().len(),
+            PARALLEL_LOAD_THRESHOLD * 2
+        );
+        assert!(repo.find_post_by_slug(&slug("post-100")).unwrap().is_some());
+    }
+}
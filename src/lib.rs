@@ -1,118 +1,439 @@
+mod api;
+mod async_repository;
 mod blog_repository;
+mod builtin;
 mod cache;
+mod callout;
+mod check;
+mod composite_repository;
+mod compression;
+mod conditional;
 mod config;
+mod diagram;
+#[cfg(feature = "embedded")]
+mod embedded;
+mod git_repository;
+mod highlight;
+mod indexed_repository;
+mod math;
+mod memory_repository;
 mod model;
 mod renderer;
-use blog_repository::FileSystemBlogRepository;
-pub use config::BlogConfig;
+mod s3_repository;
+mod slug;
+mod sqlite_repository;
+mod template_validation;
+mod theme;
+pub use api::{ContentJson, PostList, PostQuery};
+pub use blog_repository::{BlogRepository, ChangeHook, FileSystemBlogRepository, RepositoryError};
+pub use check::{CheckReport, check_blog, check_blog_with_config};
+pub use composite_repository::CompositeBlogRepository;
+pub use config::{
+    Backend, BlogConfig, HighlightMode, HighlightingConfig, TemplateValidation, TemplatesConfig,
+};
 pub use directories::{BlogDir, ContentDir};
-use model::Markdown;
+use git_repository::GitBlogRepository;
+pub use git_repository::GitContent;
+use indexed_repository::IndexedBlogRepository;
+pub use memory_repository::InMemoryBlogRepository;
+pub use model::{Markdown, Revision};
 use renderer::{BlogPostHandler, Renderer};
+use s3_repository::{S3BlogRepository, S3Client};
+pub use slug::{InvalidSlug, Slug};
+pub use sqlite_repository::ImportSummary;
+use sqlite_repository::SqliteBlogRepository;
+pub use template_validation::{TemplateIssue, TemplateIssueKind, validate_templates};
+use theme::ThemeDirs;
+pub use theme::{Theme, ThemeError, ThemeMetadata};
 
 use axum::{
-    Extension, Router,
-    extract::Path,
-    http::StatusCode,
-    response::Html,
-    routing::{get, get_service},
+    Extension, Json, Router,
+    extract::{Path, Query, Request},
+    handler::HandlerWithoutStateExt,
+    http::{HeaderMap, StatusCode, Uri, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{MethodRouter, get, get_service},
 };
+use serde::Deserialize;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tower_http::services::ServeDir;
 
-use crate::cache::CachedRenderer;
+use crate::cache::{CacheStats, CachedRenderer};
 
 pub fn create_app_with_defaults() -> Router {
     create_app_with_dirs("content", "content")
 }
 
+// Panics when the configured SQLite database cannot be opened; use
+// `create_app_with_config` to handle that.
 pub fn create_app_with_dirs<P: Into<PathBuf> + Clone>(content_dir: P, blog_dir: P) -> Router {
     let content_dir = ContentDir(content_dir.into());
-    let blog_dir = BlogDir(blog_dir.into());
     let config = BlogConfig::from_file_or_default(content_dir.config_file());
-    create_app(content_dir.into(), &blog_dir, config)
+    create_app_with_config(content_dir.dir(), blog_dir.into(), config)
+        .unwrap_or_else(|e| panic!("{e}"))
+}
+
+// Like `create_app_with_dirs` with a config that was loaded, or adjusted,
+// by the caller. `config.storage` selects where content is read from.
+pub fn create_app_with_config<P: Into<PathBuf>>(
+    content_dir: P,
+    blog_dir: P,
+    config: BlogConfig,
+) -> Result<Router, std::io::Error> {
+    let content_dir = ContentDir(content_dir.into());
+    let blog_dir = BlogDir(blog_dir.into());
+    match config.storage.backend {
+        Backend::Files => Ok(create_app(content_dir, &blog_dir, config)),
+        // Serving other content than configured would hide the mistake.
+        Backend::Sqlite => {
+            let db_path = content_dir.dir().join(&config.storage.sqlite_path);
+            let repo = SqliteBlogRepository::open(&db_path)?;
+            repo.poll_for_changes(SQLITE_POLL_INTERVAL);
+            Ok(create_app_with_repository(repo, blog_dir.0, config))
+        }
+        Backend::S3 => match create_s3_app(&blog_dir, config.clone()) {
+            Ok(app) => Ok(app),
+            Err(e) => {
+                eprintln!("Warning: {e}. Serving content from the file system instead.");
+                Ok(create_app(content_dir, &blog_dir, config))
+            }
+        },
+    }
+}
+
+// Checking for writes by other tools is a single pragma query.
+const SQLITE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Templates stay in the blog directory; posts, pages and static files come
+// from the bucket.
+fn create_s3_app(blog_dir: &BlogDir, config: BlogConfig) -> Result<Router, std::io::Error> {
+    let s3_config = config.storage.s3.clone().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "S3 backend selected without storage.s3 settings",
+        )
+    })?;
+    let repo = S3BlogRepository::new(S3Client::new(&s3_config)?, &s3_config.prefix);
+
+    let compression = config.compression.clone();
+    let cache_limits = config.cache.clone();
+    let blog_handler = BlogPostHandler::with_async_repo(config, repo.clone(), blog_dir);
+    let cached_renderer = cache_and_preload(blog_handler, compression, cache_limits);
+    repo.clear_on_change(cached_renderer.clone());
+    repo.poll_for_changes(std::time::Duration::from_secs(
+        s3_config.poll_seconds.max(1),
+    ));
+
+    let statics = get(move |uri: axum::http::Uri, headers: HeaderMap| async move {
+        s3_repository::static_object(repo.client(), repo.prefix(), uri.path(), &headers).await
+    });
+    Ok(create_router(statics, cached_renderer))
+}
+
+// Loads the posts and pages of a content directory into a SQLite database,
+// creating it when missing.
+pub fn import_into_sqlite<P: AsRef<std::path::Path>>(
+    content_dir: P,
+    db_path: P,
+) -> Result<ImportSummary, std::io::Error> {
+    let repo = SqliteBlogRepository::open(db_path.as_ref())?;
+    repo.import(content_dir.as_ref())
+        .map_err(|e| std::io::Error::other(format!("Import failed: {e:?}")))
+}
+
+// Serves posts and pages from a git repository at the given ref instead of
+// the file system. The returned handle switches the ref at runtime.
+pub fn create_app_from_git<P: Into<PathBuf>>(
+    repo_path: P,
+    reference: &str,
+    blog_dir: P,
+) -> Result<(Router, GitContent), std::io::Error> {
+    let blog_dir = BlogDir(blog_dir.into());
+    let repo = GitBlogRepository::open(&repo_path.into(), reference)?;
+    let config = repo.config();
+
+    let statics = static_handler(&blog_dir, &config);
+    let cached_renderer = create_renderer(&blog_dir, config, repo.clone());
+    let content = GitContent::new(repo, cached_renderer.clone());
+    Ok((create_router(statics, cached_renderer), content))
+}
+
+// Serves posts and pages from any repository, for example an
+// `InMemoryBlogRepository` when embedding the engine or in tests. Templates
+// and static files are still read from `blog_dir`.
+pub fn create_app_with_repository<P: Into<PathBuf>>(
+    repo: impl BlogRepository + Send + Sync + 'static,
+    blog_dir: P,
+    config: BlogConfig,
+) -> Router {
+    let blog_dir = BlogDir(blog_dir.into());
+    let statics = static_handler(&blog_dir, &config);
+    // The repository is moved into the renderer, so the hook is registered
+    // before the cache it clears exists.
+    let cache = Arc::new(std::sync::OnceLock::<CachedRenderer>::new());
+    repo.on_change({
+        let cache = cache.clone();
+        Arc::new(move || {
+            if let Some(cache) = cache.get() {
+                cache.invalidate();
+            }
+        })
+    });
+    let cached_renderer = create_renderer(&blog_dir, config, repo);
+    let _ = cache.set(cached_renderer.clone());
+    create_router(statics, cached_renderer)
+}
+
+// Serves the content, templates and static files embedded at compile time,
+// so the binary does not need a content directory at runtime.
+#[cfg(feature = "embedded")]
+pub fn create_app_embedded() -> Router {
+    let content = &embedded::CONTENT;
+    let config = embedded::config(content);
+    let theme = embedded::theme(content, &config);
+    let templates = embedded::templates(
+        content.get_dir("templates"),
+        theme.and_then(|theme| embedded::subdir(theme, "templates")),
+    );
+    let static_dirs: Vec<_> = [
+        content.get_dir("static"),
+        theme.and_then(|theme| embedded::subdir(theme, "static")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let statics = get(move |uri: Uri, headers: HeaderMap| async move {
+        let response = embedded::serve_static(&static_dirs, uri.path(), &headers);
+        if response.status() == StatusCode::NOT_FOUND {
+            return builtin::static_file(uri).await;
+        }
+        response
+    });
+    let repo = async_repository::BlockingRepository::new(embedded::repository(content));
+    let compression = config.compression.clone();
+    let cache_limits = config.cache.clone();
+    let highlighter = embedded::highlighter(content, &config);
+    let blog_handler = BlogPostHandler::with_templates(config, repo, templates, highlighter);
+
+    let cached_renderer = cache_and_preload(blog_handler, compression, cache_limits);
+    create_router(statics, cached_renderer)
 }
 
 fn create_app(content_dir: ContentDir, blog_dir: &BlogDir, config: BlogConfig) -> Router {
-    let renderer = {
+    let statics = static_handler(blog_dir, &config);
+    let cached_renderer = if config.storage.content_dirs.is_empty() {
         let repo = create_repo(content_dir.dir());
         create_renderer(blog_dir, config, repo)
+    } else {
+        let shared_dirs = config
+            .storage
+            .content_dirs
+            .iter()
+            .map(|dir| content_dir.dir().join(dir));
+        let repo = shared_dirs.fold(
+            CompositeBlogRepository::new().with_source(create_repo(content_dir.dir())),
+            |repo, dir| repo.with_source(create_repo(dir)),
+        );
+        create_renderer(blog_dir, config, repo)
     };
+    create_router(statics, cached_renderer)
+}
 
-    Router::new()
-        .route("/health", get(|| async { "I'm ok!" }))
+fn create_router(statics: MethodRouter, cached_renderer: CachedRenderer) -> Router {
+    let renderer: Arc<dyn Renderer + Send + Sync> = Arc::new(cached_renderer.clone());
+
+    let pages = Router::new()
         .route("/", get(index_handler))
         .route("/p/{slug}", get(page_handler))
         .route("/{slug}", get(post_handler))
-        .nest_service("/static", static_handler(blog_dir))
+        .route("/{slug}/history", get(history_handler))
+        .fallback(|| async { StatusCode::NOT_FOUND })
+        .layer(middleware::from_fn(error_pages));
+
+    let mut router = Router::new()
+        .route("/health", get(|| async { "I'm ok!" }))
+        .route("/health/cache", get(cache_stats_handler))
+        .route("/api/posts", get(api::posts_handler))
+        .route("/api/posts/{slug}", get(api::post_handler))
+        .route("/api/pages/{slug}", get(api::page_handler));
+    // Only highlighting with classes has a stylesheet to serve.
+    if cached_renderer.highlight_stylesheet().is_some() {
+        router = router.route(
+            highlight::STYLESHEET_PATH,
+            get(highlight_stylesheet_handler),
+        );
+    }
+    router
+        .nest_service("/static", statics)
+        .merge(pages)
         .layer(axum::extract::Extension(renderer))
+        .layer(axum::extract::Extension(cached_renderer))
 }
 
-fn static_handler(blog_dir: &BlogDir) -> axum::routing::MethodRouter {
-    let statics = blog_dir.static_dir();
-    let static_handler = get_service(ServeDir::new(statics)).handle_error(|error| async move {
+// Bare 404 and 500 responses of html URLs get the error page of the theme.
+// JSON requests and responses that already have a body are left alone.
+async fn error_pages(
+    blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let wants_json = api::wants_json(request.headers());
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let is_error = status == StatusCode::NOT_FOUND || status == StatusCode::INTERNAL_SERVER_ERROR;
+    if wants_json || !is_error || response.headers().contains_key(header::CONTENT_TYPE) {
+        return response;
+    }
+    match blog_handler.error_for(status, path).await {
+        Ok(page) => (status, page.html).into_response(),
+        Err(_) => response,
+    }
+}
+
+// Static files of the blog directory, falling back to the theme's and then
+// to the built-in ones.
+fn static_handler(blog_dir: &BlogDir, config: &BlogConfig) -> MethodRouter {
+    let [site, theme] = ThemeDirs::new(blog_dir, config).static_dirs();
+    let theme_files = ServeDir::new(theme)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(builtin::static_file.into_service());
+    let files = ServeDir::new(site)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(theme_files);
+    get_service(files).handle_error(|error| async move {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Error serving static file: {}", error),
         )
-    });
-    static_handler
+    })
 }
 
-fn create_repo<P: Into<PathBuf> + Clone>(content_dir: P) -> FileSystemBlogRepository {
-    FileSystemBlogRepository::new(content_dir.clone().into())
+fn create_repo<P: Into<PathBuf> + Clone>(content_dir: P) -> IndexedBlogRepository {
+    IndexedBlogRepository::new(FileSystemBlogRepository::new(content_dir.clone().into()))
 }
 
 fn create_renderer(
     blog_dir: &BlogDir,
     config: BlogConfig,
-    repo: FileSystemBlogRepository,
-) -> Arc<dyn Renderer + Send + Sync + 'static> {
+    repo: impl BlogRepository + Send + Sync + 'static,
+) -> CachedRenderer {
+    let compression = config.compression.clone();
+    let cache_limits = config.cache.clone();
     let blog_handler = BlogPostHandler::new(config, repo, blog_dir);
+    cache_and_preload(blog_handler, compression, cache_limits)
+}
 
-    let slugs = match blog_handler.get_all_post_slugs() {
-        Ok(slugs) => slugs,
-        Err(e) => {
-            eprintln!(
-                "Warning: Could not get post slugs for cache preloading: {:?}",
-                e
-            );
-            Vec::new()
-        }
-    };
-
-    let cached_renderer = CachedRenderer::new(Arc::new(blog_handler));
+fn cache_and_preload(
+    blog_handler: BlogPostHandler,
+    compression: config::CompressionConfig,
+    cache_limits: config::CacheConfig,
+) -> CachedRenderer {
+    let blog_handler = Arc::new(blog_handler);
+    let preload = cache_limits.preload;
+    let cached_renderer = CachedRenderer::new(blog_handler.clone(), compression, cache_limits);
     let preloaded_renderer = cached_renderer.clone();
     tokio::spawn(async move {
+        blog_handler.warn_about_unknown_languages().await;
+        if !preload {
+            return;
+        }
+        let slugs = match blog_handler.get_all_post_slugs().await {
+            Ok(slugs) => slugs,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not get post slugs for cache preloading: {:?}",
+                    e
+                );
+                Vec::new()
+            }
+        };
         if let Err(e) = preloaded_renderer.preload_posts(slugs).await {
             eprintln!("Warning: Cache preloading failed: {:?}", e);
         }
     });
 
-    let shared_renderer: Arc<dyn Renderer + Send + Sync> = Arc::new(cached_renderer);
-    shared_renderer
+    cached_renderer
+}
+
+async fn cache_stats_handler(cache: Extension<CachedRenderer>) -> Json<CacheStats> {
+    Json(cache.stats().await)
+}
+
+async fn highlight_stylesheet_handler(
+    blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
+) -> Result<Response, StatusCode> {
+    let css = blog_handler
+        .highlight_stylesheet()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(([(header::CONTENT_TYPE, "text/css")], css).into_response())
 }
 
+// The html URLs answer with the JSON of the API when the client asks for it.
 async fn index_handler(
+    uri: Uri,
+    headers: HeaderMap,
     blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
-) -> Result<Html<String>, StatusCode> {
-    blog_handler.0.posts().await
+) -> Result<Response, StatusCode> {
+    if api::wants_json(&headers) {
+        let Query(query) = Query::try_from_uri(&uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let posts = blog_handler.posts_json(query).await?;
+        return Ok(api::negotiated_json(&*posts));
+    }
+    let html = blog_handler.0.posts().await?;
+    Ok(api::vary_on_accept(conditional::respond(&headers, html)))
 }
 
 async fn page_handler(
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
+    headers: HeaderMap,
     blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
-) -> Result<Html<String>, StatusCode> {
+) -> Result<Response, StatusCode> {
+    if api::wants_json(&headers) {
+        let page = blog_handler.page_json(slug).await?;
+        return Ok(api::negotiated_json(page));
+    }
     let html = blog_handler.page_for(slug).await?;
-    Ok(html)
+    Ok(api::vary_on_accept(conditional::respond(&headers, html)))
 }
 
 async fn post_handler(
-    Path(slug): Path<String>,
+    Path(slug): Path<Slug>,
+    headers: HeaderMap,
     blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
-) -> Result<Html<String>, StatusCode> {
+) -> Result<Response, StatusCode> {
+    if api::wants_json(&headers) {
+        let post = blog_handler.post_json(slug).await?;
+        return Ok(api::negotiated_json(post));
+    }
     let html = blog_handler.post_for(slug).await?;
-    Ok(html)
+    Ok(api::vary_on_accept(conditional::respond(&headers, html)))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+async fn history_handler(
+    Path(slug): Path<Slug>,
+    Query(query): Query<HistoryQuery>,
+    headers: HeaderMap,
+    blog_handler: Extension<Arc<dyn Renderer + Send + Sync>>,
+) -> Result<Response, StatusCode> {
+    let diff = match (query.from, query.to) {
+        (Some(from), Some(to)) => Some((from, to)),
+        (None, None) => None,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let html = blog_handler.history_for(slug, diff).await?;
+    Ok(conditional::respond(&headers, html))
 }
 
 mod directories {
@@ -134,6 +455,9 @@ mod directories {
         pub fn static_dir(&self) -> PathBuf {
             self.dir().join("static")
         }
+        pub fn themes_dir(&self) -> PathBuf {
+            self.dir().join("themes")
+        }
     }
 
     impl ContentDir {
@@ -0,0 +1,385 @@
+use std::error::Error;
+use std::ops::Range;
+
+use pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag};
+use pulldown_latex::config::DisplayMode;
+use pulldown_latex::event::{Content, RelationContent};
+use pulldown_latex::{self as latex, ParserError, RenderConfig, Storage, push_mathml};
+
+// Formulas are cut out of the markdown before it is parsed, so emphasis and
+// escapes do not apply inside them, and put back as html afterwards. The
+// placeholder consists of private use characters markdown leaves alone.
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Formula {
+    pub tex: String,
+    pub display: bool,
+}
+
+// Replaces `$...$` and `$$...$$` outside code and html with placeholders.
+// Like pandoc, an inline formula must not start or end with a space and the
+// closing `$` must not be followed by a digit, so prices stay text.
+pub(crate) fn extract_formulas(markdown: &str, options: Options) -> (String, Vec<Formula>) {
+    let skipped = skipped_ranges(markdown, options);
+    let mut source = String::with_capacity(markdown.len());
+    let mut formulas = Vec::new();
+    let mut copied = 0;
+    let mut i = 0;
+    // Ranges starting at or before `i` are either behind it or skipped now.
+    let mut ahead = skipped.iter().peekable();
+    while i < markdown.len() {
+        if let Some(range) = ahead.next_if(|range| range.start <= i) {
+            i = i.max(range.end);
+            continue;
+        }
+        let rest = &markdown[i..];
+        let found = if rest.starts_with("\\") {
+            i += rest.chars().nth(1).map_or(1, |c| 1 + c.len_utf8());
+            continue;
+        } else if rest.starts_with("$$") {
+            find_closing(markdown, i + 2, "$$", &skipped).map(|end| (end, end + 2, true))
+        } else if rest.starts_with('$') {
+            find_inline_closing(markdown, i + 1, &skipped).map(|end| (end, end + 1, false))
+        } else {
+            None
+        };
+        match found {
+            Some((end, after, display)) => {
+                let open = if display { 2 } else { 1 };
+                source.push_str(&markdown[copied..i]);
+                source.push(PLACEHOLDER_START);
+                source.push_str(&formulas.len().to_string());
+                source.push(PLACEHOLDER_END);
+                formulas.push(Formula {
+                    tex: markdown[i + open..end].trim().to_string(),
+                    display,
+                });
+                copied = after;
+                i = after;
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    source.push_str(&markdown[copied..]);
+    (source, formulas)
+}
+
+// Byte ranges of code spans, code blocks, raw html, link destinations and
+// link reference definitions, so a `$` in a URL does not start a formula.
+// Sorted by start.
+fn skipped_ranges(markdown: &str, options: Options) -> Vec<Range<usize>> {
+    let parser = Parser::new_ext(markdown, options).into_offset_iter();
+    let mut skipped: Vec<Range<usize>> = parser
+        .reference_definitions()
+        .iter()
+        .map(|(_, definition)| definition.span.clone())
+        .collect();
+    // Open links with the end of their text so far; the destination follows.
+    let mut links: Vec<(Range<usize>, usize)> = Vec::new();
+    for (event, range) in parser {
+        if let Event::End(Tag::Link(kind, ..) | Tag::Image(kind, ..)) = &event
+            && let Some((link, text_end)) = links.pop()
+        {
+            skipped.push(match kind {
+                LinkType::Autolink | LinkType::Email => link,
+                _ => text_end..link.end,
+            });
+        }
+        if let Some((_, text_end)) = links.last_mut() {
+            *text_end = (*text_end).max(range.end);
+        }
+        match event {
+            Event::Start(Tag::Link(..) | Tag::Image(..)) => {
+                links.push((range.clone(), range.start + 1));
+            }
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) | Event::Html(_) => {
+                skipped.push(range);
+            }
+            _ => {}
+        }
+    }
+    skipped.sort_by_key(|range| range.start);
+    skipped
+}
+
+fn find_closing(
+    markdown: &str,
+    from: usize,
+    delimiter: &str,
+    skipped: &[Range<usize>],
+) -> Option<usize> {
+    let next = skipped.partition_point(|range| range.start < from);
+    let limit = skipped
+        .get(next)
+        .map_or(markdown.len(), |range| range.start);
+    let mut i = from;
+    while i < limit {
+        let rest = &markdown[i..limit];
+        if rest.starts_with('\\') {
+            i += rest.chars().nth(1).map_or(1, |c| 1 + c.len_utf8());
+        } else if rest.starts_with(delimiter) {
+            return Some(i);
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+fn find_inline_closing(markdown: &str, from: usize, skipped: &[Range<usize>]) -> Option<usize> {
+    if markdown[from..].starts_with(char::is_whitespace) {
+        return None;
+    }
+    let end = find_closing(markdown, from, "$", skipped)?;
+    let tex = &markdown[from..end];
+    let valid = !tex.is_empty()
+        && !tex.ends_with(char::is_whitespace)
+        && !tex.contains("\n\n")
+        && !markdown[end + 1..].starts_with(|c: char| c.is_ascii_digit());
+    valid.then_some(end)
+}
+
+// Splits text at placeholders into text and the rendered formulas.
+pub(crate) fn expand_formulas<'a>(
+    text: CowStr<'a>,
+    formulas: &[Formula],
+    events: &mut Vec<Event<'a>>,
+) {
+    if !text.contains(PLACEHOLDER_START) {
+        events.push(Event::Text(text));
+        return;
+    }
+    let mut rest: &str = &text;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let Some(length) = rest[start..].find(PLACEHOLDER_END) else {
+            break;
+        };
+        let index = &rest[start + PLACEHOLDER_START.len_utf8()..start + length];
+        let Some(formula) = index.parse().ok().and_then(|i: usize| formulas.get(i)) else {
+            break;
+        };
+        if start > 0 {
+            events.push(Event::Text(rest[..start].to_string().into()));
+        }
+        events.push(Event::Html(render(formula).into()));
+        rest = &rest[start + length + PLACEHOLDER_END.len_utf8()..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(rest.to_string().into()));
+    }
+}
+
+// Formulas that fail to parse are shown as their source.
+pub(crate) fn render(formula: &Formula) -> String {
+    match to_mathml(&formula.tex, formula.display) {
+        Ok(mathml) => mathml,
+        Err(e) => {
+            let delimiter = if formula.display { "$$" } else { "$" };
+            format!(
+                "<code class=\"math-error\" title=\"{}\">{}{}{}</code>",
+                escape(&e),
+                delimiter,
+                escape(&formula.tex),
+                delimiter
+            )
+        }
+    }
+}
+
+// The source is kept as an annotation, so copying the formula gives TeX.
+// The whole formula is parsed first, so errors are not rendered as MathML.
+pub(crate) fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let storage = Storage::new();
+    let events = latex::Parser::new(tex, &storage)
+        .collect::<Result<Vec<_>, ParserError>>()
+        .map_err(|e| {
+            e.source()
+                .map_or_else(|| e.to_string(), ToString::to_string)
+        })?;
+    // Names like the one of `\operatorname{...}` are written unescaped.
+    let names: Vec<Option<String>> = events
+        .iter()
+        .map(|event| match event {
+            latex::Event::Content(Content::Function(name)) => Some(escape(name)),
+            _ => None,
+        })
+        .collect();
+    let events = events.into_iter().zip(&names).map(|(event, name)| {
+        Ok::<_, ParserError>(match (event, name) {
+            (_, Some(name)) => latex::Event::Content(Content::Function(name)),
+            (latex::Event::Content(content), None) => {
+                latex::Event::Content(escape_operator(content))
+            }
+            (event, None) => event,
+        })
+    });
+    let annotation = escape(tex);
+    let config = RenderConfig {
+        display_mode: if display {
+            DisplayMode::Block
+        } else {
+            DisplayMode::Inline
+        },
+        annotation: Some(&annotation),
+        xml: true,
+        ..RenderConfig::default()
+    };
+    let mut mathml = String::new();
+    push_mathml(&mut mathml, events, config).map_err(|e| e.to_string())?;
+    Ok(mathml
+        .replace(ESCAPED_LT, "&lt;")
+        .replace(ESCAPED_AMP, "&amp;"))
+}
+
+// Operator characters are written unescaped and cannot be replaced by an
+// entity in the event, so `<` and `&` become noncharacters, which no
+// formula contains, and those are replaced after rendering.
+const ESCAPED_LT: char = '\u{FDD0}';
+const ESCAPED_AMP: char = '\u{FDD1}';
+
+fn escape_operator(content: Content<'_>) -> Content<'_> {
+    let escaped = |c: char| match c {
+        '<' => ESCAPED_LT,
+        '&' => ESCAPED_AMP,
+        c => c,
+    };
+    match content {
+        Content::Ordinary { content, stretchy } => Content::Ordinary {
+            content: escaped(content),
+            stretchy,
+        },
+        Content::LargeOp { content, small } => Content::LargeOp {
+            content: escaped(content),
+            small,
+        },
+        Content::BinaryOp { content, small } => Content::BinaryOp {
+            content: escaped(content),
+            small,
+        },
+        Content::Delimiter { content, size, ty } => Content::Delimiter {
+            content: escaped(content),
+            size,
+            ty,
+        },
+        Content::Punctuation(content) => Content::Punctuation(escaped(content)),
+        // The characters of a relation cannot be set. A punctuation is
+        // written as the same `<mo>`.
+        Content::Relation { content, .. } if is_less_than(content) => {
+            Content::Punctuation(ESCAPED_LT)
+        }
+        content => content,
+    }
+}
+
+fn is_less_than(relation: RelationContent) -> bool {
+    relation.encode_utf8_to_buf(&mut [0; 8]) == b"<"
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark::escape::escape_html(&mut escaped, text).unwrap_or_default();
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formulas(markdown: &str) -> (String, Vec<Formula>) {
+        extract_formulas(markdown, Options::empty())
+    }
+
+    #[test]
+    fn converts_to_mathml_with_the_source_as_annotation() {
+        let inline = to_mathml("x^2", false).unwrap();
+        let display = to_mathml(r"\sum_{i=1}^n i", true).unwrap();
+
+        assert!(inline.starts_with("<math display=\"inline\""));
+        assert!(inline.contains("<msup><mi>x</mi><mn>2</mn></msup>"));
+        assert!(inline.contains("<annotation encoding=\"application/x-tex\">x^2</annotation>"));
+        assert!(display.starts_with("<math display=\"block\""));
+        assert!(display.contains("<munderover>"));
+    }
+
+    #[test]
+    fn escapes_markup_in_formulas() {
+        let mathml = to_mathml(r"\operatorname{<b>} < \& \text{<i>}", false).unwrap();
+
+        assert!(mathml.contains("<mi>&lt;b&gt;</mi>"));
+        assert!(mathml.contains("<mo>&lt;</mo>"));
+        assert!(mathml.contains(">&amp;</"));
+        assert!(!mathml.contains('\u{FDD0}') && !mathml.contains('\u{FDD1}'));
+        assert!(!mathml.contains("<b>") && !mathml.contains("<i>"));
+    }
+
+    #[test]
+    fn reports_invalid_formulas() {
+        assert!(to_mathml(r"\foo", false).is_err());
+        assert!(to_mathml("{x", false).is_err());
+        assert!(to_mathml("x}", false).is_err());
+        assert_eq!(
+            render(&Formula {
+                tex: r"a < \foo".to_string(),
+                display: false
+            }),
+            "<code class=\"math-error\" title=\"unknown primitive command found\">$a &lt; \\foo$</code>"
+        );
+    }
+
+    #[test]
+    fn finds_formulas_outside_code() {
+        let (source, found) =
+            formulas("Costs $5 and $10, $a_1$ and `$b$`.\n\n$$\nx\n$$\n\n```\n$c$\n```\n\\$d$");
+
+        assert_eq!(
+            found,
+            vec![
+                Formula {
+                    tex: "a_1".to_string(),
+                    display: false
+                },
+                Formula {
+                    tex: "x".to_string(),
+                    display: true
+                },
+            ]
+        );
+        assert_eq!(
+            source,
+            "Costs $5 and $10, \u{E000}0\u{E001} and `$b$`.\n\n\u{E000}1\u{E001}\n\n```\n$c$\n```\n\\$d$"
+        );
+    }
+
+    #[test]
+    fn skips_link_destinations() {
+        let markdown = "[$a$](https://example.com/$b$/) ![$c$](/$d$.png) <https://example.com/$e$>\n\
+                        [ref] $f$\n\n[ref]: https://example.com/$g$/";
+
+        let (source, found) = formulas(markdown);
+
+        let tex: Vec<&str> = found.iter().map(|formula| formula.tex.as_str()).collect();
+        assert_eq!(tex, vec!["a", "c", "f"]);
+        assert!(source.contains("(https://example.com/$b$/)"));
+        assert!(source.contains("(/$d$.png)"));
+        assert!(source.contains("<https://example.com/$e$>"));
+        assert!(source.ends_with("[ref]: https://example.com/$g$/"));
+    }
+
+    #[test]
+    fn expands_placeholders_into_html() {
+        let formula = vec![Formula {
+            tex: "x".to_string(),
+            display: false,
+        }];
+        let mut events = Vec::new();
+
+        expand_formulas("a \u{E000}0\u{E001} b".into(), &formula, &mut events);
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], Event::Text("a ".into()));
+        assert!(matches!(&events[1], Event::Html(html) if html.starts_with("<math")));
+        assert_eq!(events[2], Event::Text(" b".into()));
+    }
+}
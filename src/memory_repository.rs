@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::Markdown;
+use crate::blog_repository::{BlogRepository, ChangeHook, RepositoryError, sort_posts};
+use crate::slug::Slug;
+
+// Posts and pages parsed from strings instead of files. Clones share their
+// content, so a handle kept by the caller can add posts after the router
+// has been built. Rendered html is cached as configured in `BlogConfig` and
+// cleared on every change.
+#[derive(Clone, Default)]
+pub struct InMemoryBlogRepository {
+    content: Arc<RwLock<Content>>,
+    hooks: Arc<Mutex<Vec<ChangeHook>>>,
+}
+
+#[derive(Default)]
+struct Content {
+    // Keyed by name, which plays the role of the file name without extension.
+    posts: HashMap<String, Markdown>,
+    pages: HashMap<String, Markdown>,
+}
+
+impl InMemoryBlogRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_post(self, name: &str, markdown: &str) -> Self {
+        self.add_post(name, markdown);
+        self
+    }
+
+    pub fn with_page(self, name: &str, markdown: &str) -> Self {
+        self.add_page(name, markdown);
+        self
+    }
+
+    // Replaces an existing post of the same name.
+    pub fn add_post(&self, name: &str, markdown: &str) {
+        let mut post = Markdown::parse(markdown);
+        post.slugs.push(name.to_string());
+        post.modified = Some(chrono::Utc::now());
+        self.write().posts.insert(name.to_string(), post);
+        self.changed();
+    }
+
+    pub fn add_page(&self, name: &str, markdown: &str) {
+        let page = Markdown {
+            title: None,
+            content: markdown.to_string(),
+            slugs: vec![name.to_string()],
+            publish_date: None,
+            modified: Some(chrono::Utc::now()),
+            updated: None,
+            front_matter: None,
+        };
+        self.write().pages.insert(name.to_string(), page);
+        self.changed();
+    }
+
+    pub fn remove_post(&self, name: &str) -> bool {
+        let removed = self.write().posts.remove(name).is_some();
+        self.changed();
+        removed
+    }
+
+    pub fn remove_page(&self, name: &str) -> bool {
+        let removed = self.write().pages.remove(name).is_some();
+        self.changed();
+        removed
+    }
+
+    fn changed(&self) {
+        let hooks = self
+            .hooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for changed in hooks.iter() {
+            changed();
+        }
+    }
+
+    fn read(&self) -> Result<std::sync::RwLockReadGuard<'_, Content>, RepositoryError> {
+        self.content
+            .read()
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+
+    // A poisoned lock only means a writer panicked between two inserts,
+    // which leaves the maps consistent.
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Content> {
+        self.content
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl BlogRepository for InMemoryBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        let mut posts: Vec<Markdown> = self.read()?.posts.values().cloned().collect();
+        sort_posts(&mut posts);
+        Ok(posts.into())
+    }
+
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let content = self.read()?;
+        Ok(content
+            .posts
+            .get(slug.as_str())
+            .filter(|post| post.contains(slug.to_string()))
+            .or_else(|| {
+                content
+                    .posts
+                    .values()
+                    .find(|post| post.contains(slug.to_string()))
+            })
+            .cloned())
+    }
+
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        Ok(self.read()?.pages.get(slug.as_str()).cloned())
+    }
+
+    fn on_change(&self, changed: ChangeHook) {
+        self.hooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slug::slug;
+
+    #[test]
+    fn finds_posts_by_front_matter_slug_and_name() {
+        let repo = InMemoryBlogRepository::new().with_post("name", "---\nslug: a\n---\n");
+
+        assert!(repo.find_post_by_slug(&slug("a")).unwrap().is_some());
+        assert!(repo.find_post_by_slug(&slug("name")).unwrap().is_some());
+        assert!(repo.find_post_by_slug(&slug("other")).unwrap().is_none());
+    }
+
+    #[test]
+    fn sorts_posts_newest_first() {
+        let repo = InMemoryBlogRepository::new()
+            .with_post("old", "---\ntitle: Old\ndatePublished: 2020-01-01\n---\n")
+            .with_post("new", "---\ntitle: New\ndatePublished: 2022-01-01\n---\n");
+
+        let titles: Vec<String> = repo
+            .get_all_posts()
+            .unwrap()
+            .iter()
+            .filter_map(|post| post.title.clone())
+            .collect();
+
+        assert_eq!(titles, vec!["New", "Old"]);
+    }
+
+    #[test]
+    fn clones_share_content() {
+        let repo = InMemoryBlogRepository::new();
+        let handle = repo.clone();
+
+        handle.add_post("later", "Added later");
+        handle.add_page("about", "About me");
+
+        assert!(repo.find_post_by_slug(&slug("later")).unwrap().is_some());
+        assert_eq!(
+            repo.get_page(&slug("about")).unwrap().unwrap().content,
+            "About me"
+        );
+        assert!(handle.remove_post("later"));
+        assert!(repo.get_all_posts().unwrap().is_empty());
+    }
+}
@@ -7,21 +7,58 @@ pub struct FrontMatter {
     pub publish_date: Option<String>,
     #[serde(default)]
     pub slug: Option<String>,
+    // Dates or RFC 3339 times. Git history only fills in missing ones.
+    #[serde(alias = "dateModified")]
+    pub modified: Option<String>,
+    pub updated: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Markdown {
     pub title: Option<String>,
     pub content: String,
     pub slugs: Vec<String>,
     pub publish_date: Option<chrono::NaiveDate>,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+    // Time of the latest revision when the content is under version control,
+    // unless the front matter sets it.
+    pub updated: Option<chrono::DateTime<chrono::Utc>>,
+    // All front matter keys, including ones the engine does not interpret.
+    pub front_matter: Option<serde_json::Value>,
+}
+
+// A commit that changed a post.
+#[derive(Clone, Debug)]
+pub struct Revision {
+    pub hash: String,
+    pub message: String,
+    pub date: chrono::DateTime<chrono::Utc>,
 }
 
 pub struct ParsedContent {
     pub front_matter: Option<FrontMatter>,
+    pub front_matter_values: Option<serde_json::Value>,
+    pub front_matter_error: Option<String>,
     pub content: String,
 }
 
+#[derive(serde::Serialize, Debug)]
+pub struct RevisionEntry {
+    pub hash: String,
+    pub short_hash: String,
+    pub message: String,
+    pub date: String,
+    // Hash of the revision before this one, to link a diff.
+    pub previous_hash: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DiffLine {
+    // "added", "removed" or "context"
+    pub kind: &'static str,
+    pub text: String,
+}
+
 #[derive(serde::Serialize, Debug)]
 pub struct BlogPost {
     pub title: String,
@@ -30,8 +67,13 @@ pub struct BlogPost {
 }
 
 impl Markdown {
-    pub fn from_str(text: &str) -> Self {
-        let parsed = Self::parse_front_matter(text);
+    // Front matter that fails to parse is ignored; `parse_front_matter`
+    // returns the error.
+    pub fn parse(text: &str) -> Self {
+        Self::from_parsed(Self::parse_front_matter(text))
+    }
+
+    pub fn from_parsed(parsed: ParsedContent) -> Self {
         match parsed.front_matter {
             Some(front_matter) => Markdown {
                 title: front_matter.title,
@@ -40,12 +82,18 @@ impl Markdown {
                     .publish_date
                     .and_then(|s| parse_date_for_sorting(s.as_str())),
                 slugs: front_matter.slug.into_iter().collect(),
+                modified: front_matter.modified.as_deref().and_then(parse_time),
+                updated: front_matter.updated.as_deref().and_then(parse_time),
+                front_matter: parsed.front_matter_values,
             },
             None => Markdown {
                 title: None,
                 content: parsed.content,
                 publish_date: None,
                 slugs: vec![],
+                modified: None,
+                updated: None,
+                front_matter: None,
             },
         }
     }
@@ -63,7 +111,25 @@ impl Markdown {
             .to_string()
     }
 
-    fn parse_front_matter(content: &str) -> ParsedContent {
+    // A boolean front matter key like `math: true`; missing means false.
+    pub fn front_matter_flag(&self, key: &str) -> bool {
+        self.front_matter
+            .as_ref()
+            .and_then(|values| values.get(key))
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    // File modification time when known, otherwise the start of the publish date.
+    pub fn last_modified(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.modified.or_else(|| {
+            self.publish_date
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|datetime| datetime.and_utc())
+        })
+    }
+
+    pub fn parse_front_matter(content: &str) -> ParsedContent {
         use gray_matter::Matter;
         use gray_matter::engine::YAML;
 
@@ -73,17 +139,28 @@ impl Markdown {
         let yaml_text = result.matter;
         let content = result.content;
 
-        let front_matter = match serde_yaml::from_str::<FrontMatter>(yaml_text.as_str()) {
-            Ok(front_matter) => Some(front_matter),
-            Err(e) => {
-                eprintln!("Error parsing front matter: {}", e);
-                None
-            }
-        };
-
-        ParsedContent {
-            front_matter,
-            content,
+        if yaml_text.trim().is_empty() {
+            return ParsedContent {
+                front_matter: None,
+                front_matter_values: None,
+                front_matter_error: None,
+                content,
+            };
+        }
+
+        match serde_yaml::from_str::<FrontMatter>(yaml_text.as_str()) {
+            Ok(front_matter) => ParsedContent {
+                front_matter: Some(front_matter),
+                front_matter_values: serde_yaml::from_str(yaml_text.as_str()).ok(),
+                front_matter_error: None,
+                content,
+            },
+            Err(e) => ParsedContent {
+                front_matter: None,
+                front_matter_values: None,
+                front_matter_error: Some(e.to_string()),
+                content,
+            },
         }
     }
 }
@@ -117,6 +194,16 @@ pub fn parse_date_for_sorting(date_str: &str) -> Option<chrono::NaiveDate> {
     None
 }
 
+// An RFC 3339 time, or any date `parse_date_for_sorting` accepts at midnight UTC.
+pub fn parse_time(time_str: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    match chrono::DateTime::parse_from_rfc3339(time_str) {
+        Ok(time) => Some(time.to_utc()),
+        Err(_) => parse_date_for_sorting(time_str)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc()),
+    }
+}
+
 pub fn format_date_for_post_view(date: chrono::NaiveDate) -> String {
     date.format("%B %d, %Y").to_string()
 }
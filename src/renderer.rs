@@ -1,77 +1,177 @@
 use async_trait::async_trait;
 use axum::http::StatusCode;
 use axum::response::Html;
+use chrono::{DateTime, Utc};
 use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, html};
+use std::borrow::Cow;
 use std::sync::Arc;
-
-use syntect::highlighting::{Theme, ThemeSet};
-use syntect::html::highlighted_html_for_string;
-use syntect::parsing::SyntaxSet;
 use tera::{Context, Tera};
 
 use crate::BlogDir;
+use crate::api::{ContentJson, PostList, PostQuery};
+use crate::async_repository::{AsyncBlogRepository, BlockingRepository};
 use crate::blog_repository::{BlogRepository, RepositoryError};
-use crate::config::BlogConfig;
-use crate::model::{BlogPost, Markdown, format_date_for_post_view, format_date_for_posts_overview};
+use crate::builtin;
+use crate::callout;
+use crate::compression::Compressed;
+use crate::config::{BlogConfig, HighlightMode};
+use crate::diagram::{self, DiagramCache};
+use crate::highlight::{self, CodeBlockInfo, Highlighter};
+use crate::math;
+use crate::model::{
+    BlogPost, DiffLine, Markdown, Revision, RevisionEntry, format_date_for_post_view,
+    format_date_for_posts_overview,
+};
+use crate::slug::Slug;
+use crate::template_validation::{self, TemplateIssue};
+use crate::theme::{Theme, ThemeDirs};
+
+pub type ThreadSafeBlogRepository = Arc<dyn AsyncBlogRepository + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub struct RenderedHtml {
+    pub html: Html<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+    // Set once the html is stored in the cache.
+    pub etag: Option<String>,
+    pub compressed: Option<Compressed>,
+}
 
-pub type ThreadSafeBlogRepository = Arc<dyn BlogRepository + Send + Sync>;
+impl RenderedHtml {
+    pub(crate) fn new(html: String, last_modified: Option<DateTime<Utc>>) -> Self {
+        Self {
+            html: Html(html),
+            last_modified,
+            etag: None,
+            compressed: None,
+        }
+    }
+}
 
 #[async_trait]
 pub trait Renderer {
-    async fn post_for(&self, slug: String) -> Result<Html<String>, StatusCode>;
-    async fn page_for(&self, slug: String) -> Result<Html<String>, StatusCode>;
-    async fn posts(&self) -> Result<Html<String>, StatusCode>;
+    async fn post_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode>;
+    async fn page_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode>;
+    async fn posts(&self) -> Result<RenderedHtml, StatusCode>;
+    // Revision list of a post, or the diff between two of its revisions.
+    async fn history_for(
+        &self,
+        slug: Slug,
+        diff: Option<(String, String)>,
+    ) -> Result<RenderedHtml, StatusCode>;
+    async fn post_json(&self, slug: Slug) -> Result<ContentJson, StatusCode>;
+    async fn page_json(&self, slug: Slug) -> Result<ContentJson, StatusCode>;
+    async fn posts_json(&self, query: PostQuery) -> Result<Arc<PostList>, StatusCode>;
+    // Colors of highlighted code in class mode.
+    fn highlight_stylesheet(&self) -> Option<String>;
+    // The `404.html` or `500.html` page shown for an html request to `path`.
+    async fn error_for(&self, status: StatusCode, path: String)
+    -> Result<RenderedHtml, StatusCode>;
 }
 
+// Clones share everything, so they are cheap.
 #[derive(Clone)]
 pub struct BlogPostHandler {
     repo: ThreadSafeBlogRepository,
-    templates: Tera,
-    config: BlogConfig,
-    syntax_set: SyntaxSet,
-    theme: Theme,
+    templates: Arc<Tera>,
+    config: Arc<BlogConfig>,
+    highlighter: Arc<Highlighter>,
+    diagrams: Arc<DiagramCache>,
 }
 
 impl BlogPostHandler {
+    // Synchronous repositories are run on the blocking thread pool.
     pub fn new(
         config: BlogConfig,
         blog_repo: impl BlogRepository + Send + Sync + 'static,
         blog_dir: &BlogDir,
     ) -> Self {
-        let template_path = format!("{}{}", blog_dir.templates_dir().display(), "/**/*.html");
-        let templates = match Tera::new(&template_path) {
+        Self::with_async_repo(config, BlockingRepository::new(blog_repo), blog_dir)
+    }
+
+    pub fn with_async_repo(
+        config: BlogConfig,
+        blog_repo: impl AsyncBlogRepository + Send + Sync + 'static,
+        blog_dir: &BlogDir,
+    ) -> Self {
+        if let Err(e) = Theme::load(blog_dir, &config) {
+            eprintln!("Warning: {e}");
+        }
+        let templates = match ThemeDirs::new(blog_dir, &config).templates() {
             Ok(t) => t,
             Err(e) => {
-                eprintln!("Template parsing error(s): {}", e);
-                Tera::default()
+                TemplateIssue::parse(&e).warn();
+                log::warn!("Serving the built-in templates instead");
+                builtin::templates()
             }
         };
+        let highlighter = Highlighter::new(&config.highlighting, &blog_dir.dir());
+        Self::with_templates(config, blog_repo, templates, highlighter)
+    }
 
+    // For templates and highlighting themes that do not come from the blog
+    // directory. Issues found by the template check are logged; refusing to
+    // start on them in strict mode is up to the caller.
+    pub(crate) fn with_templates(
+        config: BlogConfig,
+        blog_repo: impl AsyncBlogRepository + Send + Sync + 'static,
+        templates: Tera,
+        highlighter: Highlighter,
+    ) -> Self {
+        for issue in template_validation::check_templates(&templates, &config) {
+            issue.warn();
+        }
         let repo = Arc::new(blog_repo);
 
-        let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme = ThemeSet::load_defaults().themes["base16-ocean.dark"].clone();
-
         Self {
             repo,
-            templates,
-            config,
-            syntax_set,
-            theme,
+            templates: Arc::new(templates),
+            config: Arc::new(config),
+            highlighter: Arc::new(highlighter),
+            diagrams: Arc::default(),
         }
     }
 
-    pub fn get_all_post_slugs(&self) -> Result<Vec<String>, StatusCode> {
-        let markdowns = self.repo.get_all_posts().map_err(Self::into)?;
+    // Posts whose slug cannot be requested are left out.
+    pub async fn get_all_post_slugs(&self) -> Result<Vec<Slug>, StatusCode> {
+        let markdowns = self.repo.get_all_posts().await.map_err(Self::into)?;
         let slugs = markdowns
-            .into_iter()
-            .map(|markdown| markdown.primary_slug())
+            .iter()
+            .filter_map(|markdown| Slug::parse(&markdown.primary_slug()).ok())
             .collect();
         Ok(slugs)
     }
 
-    pub async fn render_posts(&self) -> Result<Html<String>, StatusCode> {
-        let posts = self.get_all_posts()?;
+    // Posts with code blocks in languages without a syntax are shown with
+    // plain code, which is easy to miss, so they are listed at startup.
+    pub async fn warn_about_unknown_languages(&self) {
+        let Ok(markdowns) = self.repo.get_all_posts().await else {
+            return;
+        };
+        let posts: Vec<String> = markdowns
+            .iter()
+            .filter_map(|markdown| {
+                let languages = self.unknown_languages(&markdown.content);
+                (!languages.is_empty())
+                    .then(|| format!("{} ({})", markdown.primary_slug(), languages.join(", ")))
+            })
+            .collect();
+        if !posts.is_empty() {
+            eprintln!(
+                "Warning: Code blocks in unknown languages are not highlighted in: {}",
+                posts.join(", ")
+            );
+        }
+    }
+
+    pub(crate) fn unknown_languages(&self, markdown: &str) -> Vec<String> {
+        self.highlighter.unknown_languages(markdown)
+    }
+
+    pub async fn render_posts(&self) -> Result<RenderedHtml, StatusCode> {
+        let markdowns = self.repo.get_all_posts().await.map_err(Self::into)?;
+        let last_modified = markdowns.iter().filter_map(Markdown::last_modified).max();
+        let posts = Self::to_blog_posts(&markdowns);
 
         let mut context = self.build_base_context("/");
         context.insert("posts", &posts);
@@ -82,32 +182,29 @@ impl BlogPostHandler {
                 eprintln!("Template error: {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
             })
-            .map(Html)
+            .map(|html| RenderedHtml::new(html, last_modified))
     }
 
-    fn get_all_posts(&self) -> Result<Vec<BlogPost>, StatusCode> {
-        let markdowns = self.repo.get_all_posts().map_err(Self::into)?;
-
-        let posts = markdowns
-            .into_iter()
+    fn to_blog_posts(markdowns: &[Markdown]) -> Vec<BlogPost> {
+        markdowns
+            .iter()
             .map(|markdown| BlogPost {
                 title: markdown.title.clone().unwrap_or("Untitled".to_string()),
                 publish_date: markdown.publish_date.map(format_date_for_posts_overview),
                 slug: markdown.primary_slug(),
             })
-            .collect();
-
-        Ok(posts)
+            .collect()
     }
 
-    pub async fn render_page(&self, slug: String) -> Result<Html<String>, StatusCode> {
+    pub async fn render_page(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
         log::info!("Requested page: {}", &slug);
-        let page = self.repo.get_page(&slug).map_err(Self::into)?;
+        let page = self.repo.get_page(&slug).await.map_err(Self::into)?;
         let markdown = page.ok_or(StatusCode::NOT_FOUND)?;
 
         let mut context = self.build_base_context(&format!("/p/{}", slug));
         self.insert_content(&markdown, &mut context);
         Self::insert_title(&markdown, &mut context);
+        let last_modified = markdown.last_modified();
 
         self.templates
             .render("page.html", &context)
@@ -115,16 +212,33 @@ impl BlogPostHandler {
                 eprintln!("Template error: {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
             })
-            .map(Html)
+            .map(|html| RenderedHtml::new(html, last_modified))
     }
 
-    pub async fn render_post(&self, slug: String) -> Result<Html<String>, StatusCode> {
-        let post = self.repo.find_post_by_slug(&slug).map_err(Self::into)?;
+    pub async fn render_post(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
+        let post = self
+            .repo
+            .find_post_by_slug(&slug)
+            .await
+            .map_err(Self::into)?;
         let markdown = post.ok_or(StatusCode::NOT_FOUND)?;
 
         let mut context = self.build_base_context(&format!("/{}", slug));
         self.insert_content(&markdown, &mut context);
         Self::insert_title(&markdown, &mut context);
+        let last_modified = markdown.last_modified();
+        context.insert("slug", &slug);
+        if let Some(updated) = markdown.updated {
+            context.insert("updated", &format_date_for_post_view(updated.date_naive()));
+        }
+        // Only backends that track revisions serve `/{slug}/history`.
+        let has_history = self
+            .repo
+            .post_history(&slug)
+            .await
+            .map_err(Self::into)?
+            .is_some_and(|revisions| !revisions.is_empty());
+        context.insert("has_history", &has_history);
         Self::insert_published_date(markdown, &mut context);
 
         self.templates
@@ -133,7 +247,136 @@ impl BlogPostHandler {
                 eprintln!("Template error: {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
             })
-            .map(Html)
+            .map(|html| RenderedHtml::new(html, last_modified))
+    }
+
+    pub async fn render_error(
+        &self,
+        status: StatusCode,
+        path: &str,
+    ) -> Result<RenderedHtml, StatusCode> {
+        let mut context = self.build_base_context(path);
+        context.insert("status", &status.as_u16());
+
+        self.templates
+            .render(&format!("{}.html", status.as_u16()), &context)
+            .map_err(|e| {
+                eprintln!("Template error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+            .map(|html| RenderedHtml::new(html, None))
+    }
+
+    pub async fn post_to_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        let post = self
+            .repo
+            .find_post_by_slug(&slug)
+            .await
+            .map_err(Self::into)?;
+        let markdown = post.ok_or(StatusCode::NOT_FOUND)?;
+        let html = self.parse_to_html(&markdown);
+        Ok(ContentJson::new(markdown, html))
+    }
+
+    pub async fn page_to_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        let page = self.repo.get_page(&slug).await.map_err(Self::into)?;
+        let markdown = page.ok_or(StatusCode::NOT_FOUND)?;
+        let html = self.parse_to_html(&markdown);
+        Ok(ContentJson::new(markdown, html))
+    }
+
+    pub async fn posts_to_json(&self, query: PostQuery) -> Result<PostList, StatusCode> {
+        let markdowns = self.repo.get_all_posts().await.map_err(Self::into)?;
+        let mut selection = query.select(&markdowns)?;
+        let posts = std::mem::take(&mut selection.posts)
+            .into_iter()
+            .map(|markdown| {
+                let html = self.parse_to_html(&markdown);
+                ContentJson::new(markdown, html)
+            })
+            .collect();
+        Ok(PostList::new(selection, posts))
+    }
+
+    // Not found for posts that are not under version control.
+    pub async fn render_history(
+        &self,
+        slug: Slug,
+        diff: Option<(String, String)>,
+    ) -> Result<RenderedHtml, StatusCode> {
+        let post = self
+            .repo
+            .find_post_by_slug(&slug)
+            .await
+            .map_err(Self::into)?;
+        let markdown = post.ok_or(StatusCode::NOT_FOUND)?;
+        let revisions = self
+            .repo
+            .post_history(&slug)
+            .await
+            .map_err(Self::into)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+
+        let mut context = self.build_base_context(&format!("/{}/history", slug));
+        Self::insert_title(&markdown, &mut context);
+        context.insert("slug", &slug);
+        context.insert("revisions", &Self::to_revision_entries(&revisions));
+
+        if let Some((from, to)) = diff {
+            let patch = self
+                .repo
+                .post_diff(&slug, &from, &to)
+                .await
+                .map_err(Self::into)?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            context.insert("diff", &Self::to_diff_lines(&patch));
+            context.insert("from", &short_hash(&from));
+            context.insert("to", &short_hash(&to));
+        }
+
+        let last_modified = revisions.first().map(|revision| revision.date);
+        self.templates
+            .render("history.html", &context)
+            .map_err(|e| {
+                eprintln!("Template error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+            .map(|html| RenderedHtml::new(html, last_modified))
+    }
+
+    // Revisions come newest first; each links to a diff against the one
+    // before it.
+    fn to_revision_entries(revisions: &[Revision]) -> Vec<RevisionEntry> {
+        revisions
+            .iter()
+            .enumerate()
+            .map(|(position, revision)| RevisionEntry {
+                hash: revision.hash.clone(),
+                short_hash: short_hash(&revision.hash),
+                message: revision.message.clone(),
+                date: format_date_for_post_view(revision.date.date_naive()),
+                previous_hash: revisions
+                    .get(position + 1)
+                    .map(|previous| previous.hash.clone()),
+            })
+            .collect()
+    }
+
+    fn to_diff_lines(patch: &str) -> Vec<DiffLine> {
+        patch
+            .lines()
+            .map(|line| {
+                let kind = match line.chars().next() {
+                    Some('+') if !line.starts_with("+++") => "added",
+                    Some('-') if !line.starts_with("---") => "removed",
+                    _ => "context",
+                };
+                DiffLine {
+                    kind,
+                    text: line.to_string(),
+                }
+            })
+            .collect()
     }
 
     fn into(repo_err: RepositoryError) -> StatusCode {
@@ -144,17 +387,7 @@ impl BlogPostHandler {
     }
 
     fn build_base_context(&self, path: &str) -> Context {
-        let mut context = Context::new();
-
-        let config = &self.config;
-
-        let now = chrono::Local::now();
-        context.insert("now", &now.to_rfc3339());
-        context.insert("current_url", path);
-        context.insert("site_title", &config.site_title);
-        context.insert("site_description", &config.site_description);
-
-        context
+        base_context(&self.config, path)
     }
 
     fn insert_content(&self, markdown: &Markdown, context: &mut Context) {
@@ -183,41 +416,41 @@ impl BlogPostHandler {
         options.insert(pulldown_cmark::Options::ENABLE_TASKLISTS);
         options.insert(pulldown_cmark::Options::ENABLE_SMART_PUNCTUATION);
 
-        let parser = Parser::new_ext(&markdown.content, options);
+        // Formulas are only looked for in posts that ask for them, so a `$`
+        // in other posts stays text.
+        let (content, formulas) = match markdown.front_matter_flag("math") {
+            true => {
+                let (content, formulas) = math::extract_formulas(&markdown.content, options);
+                (Cow::Owned(content), formulas)
+            }
+            false => (Cow::Borrowed(markdown.content.as_str()), Vec::new()),
+        };
+        let parser = Parser::new_ext(&content, options);
         let mut events = Vec::new();
-        let mut code_block: Option<(String, String)> = None;
+        let mut code_block: Option<(CodeBlockInfo, String)> = None;
 
         for event in parser {
             match event {
                 Event::Start(Tag::CodeBlock(kind)) => {
-                    let lang = match kind {
-                        CodeBlockKind::Fenced(lang) => lang.into_string(),
-                        CodeBlockKind::Indented => "text".to_string(),
+                    let info = match kind {
+                        CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(&info),
+                        CodeBlockKind::Indented => CodeBlockInfo::default(),
                     };
-                    code_block = Some((lang, String::new()));
+                    code_block = Some((info, String::new()));
                 }
                 Event::Text(text) => {
                     if let Some((_, content)) = &mut code_block {
                         content.push_str(&text);
                     } else {
-                        events.push(Event::Text(text));
+                        math::expand_formulas(text, &formulas, &mut events);
                     }
                 }
                 Event::End(Tag::CodeBlock(_)) => {
-                    if let Some((lang, content)) = code_block.take() {
-                        let syntax = self
-                            .syntax_set
-                            .find_syntax_by_token(&lang)
-                            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
-
-                        let highlighted_code = highlighted_html_for_string(
-                            &content,
-                            &self.syntax_set,
-                            syntax,
-                            &self.theme,
-                        )
-                        .unwrap_or_else(|_| content.clone());
-                        let html = format!("<code>{}</code>", highlighted_code);
+                    if let Some((info, content)) = code_block.take() {
+                        let html = match diagram::is_diagram(&info) {
+                            true => self.diagrams.figure(&content, &info),
+                            false => self.highlighter.code_block(&content, &info),
+                        };
                         events.push(Event::Html(html.into()));
                     }
                 }
@@ -228,22 +461,77 @@ impl BlogPostHandler {
         }
 
         let mut html_output = String::new();
-        html::push_html(&mut html_output, events.into_iter());
+        html::push_html(&mut html_output, callout::callouts(events).into_iter());
         html_output
     }
 }
 
 #[async_trait]
 impl Renderer for BlogPostHandler {
-    async fn posts(&self) -> Result<Html<String>, StatusCode> {
+    async fn posts(&self) -> Result<RenderedHtml, StatusCode> {
         BlogPostHandler::render_posts(self).await
     }
 
-    async fn post_for(&self, slug: String) -> Result<Html<String>, StatusCode> {
+    async fn post_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
         BlogPostHandler::render_post(self, slug).await
     }
 
-    async fn page_for(&self, slug: String) -> Result<Html<String>, StatusCode> {
+    async fn page_for(&self, slug: Slug) -> Result<RenderedHtml, StatusCode> {
         BlogPostHandler::render_page(self, slug).await
     }
+
+    async fn history_for(
+        &self,
+        slug: Slug,
+        diff: Option<(String, String)>,
+    ) -> Result<RenderedHtml, StatusCode> {
+        BlogPostHandler::render_history(self, slug, diff).await
+    }
+
+    async fn post_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        BlogPostHandler::post_to_json(self, slug).await
+    }
+
+    async fn page_json(&self, slug: Slug) -> Result<ContentJson, StatusCode> {
+        BlogPostHandler::page_to_json(self, slug).await
+    }
+
+    async fn posts_json(&self, query: PostQuery) -> Result<Arc<PostList>, StatusCode> {
+        BlogPostHandler::posts_to_json(self, query)
+            .await
+            .map(Arc::new)
+    }
+
+    async fn error_for(
+        &self,
+        status: StatusCode,
+        path: String,
+    ) -> Result<RenderedHtml, StatusCode> {
+        BlogPostHandler::render_error(self, status, &path).await
+    }
+
+    fn highlight_stylesheet(&self) -> Option<String> {
+        self.highlighter.stylesheet().map(str::to_string)
+    }
+}
+
+// Variables every template gets.
+pub(crate) fn base_context(config: &BlogConfig, path: &str) -> Context {
+    let mut context = Context::new();
+
+    let now = chrono::Local::now();
+    context.insert("now", &now.to_rfc3339());
+    context.insert("current_url", path);
+    context.insert("site_title", &config.site_title);
+    context.insert("site_description", &config.site_description);
+    context.insert("extra", &config.extra);
+    if config.highlighting.mode == HighlightMode::Classes {
+        context.insert("highlight_stylesheet", highlight::STYLESHEET_PATH);
+    }
+
+    context
+}
+
+fn short_hash(hash: &str) -> String {
+    hash.chars().take(7).collect()
 }
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// A post or page name taken from a URL. Slugs end up in file paths and
+// object keys, so anything that could leave the directory it is looked up
+// in is rejected: separators, `..`, NUL and other control characters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Slug(String);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidSlug(String);
+
+impl Slug {
+    pub fn parse(slug: &str) -> Result<Self, InvalidSlug> {
+        let invalid = slug.is_empty()
+            || slug == "."
+            || slug.contains("..")
+            || slug
+                .chars()
+                .any(|c| c == '/' || c == '\\' || c.is_control());
+        if invalid {
+            return Err(InvalidSlug(slug.to_string()));
+        }
+        Ok(Slug(slug.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Slug {
+    type Error = InvalidSlug;
+
+    fn try_from(slug: String) -> Result<Self, Self::Error> {
+        Slug::parse(&slug)
+    }
+}
+
+impl fmt::Display for Slug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for InvalidSlug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid slug: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSlug {}
+
+// For tests that look up content by a literal slug.
+#[cfg(test)]
+pub(crate) fn slug(slug: &str) -> Slug {
+    Slug::parse(slug).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_names() {
+        for valid in ["hello", "hello-world", "2023_recap", "v1.2", "über"] {
+            assert_eq!(Slug::parse(valid).unwrap().as_str(), valid);
+        }
+    }
+
+    #[test]
+    fn rejects_names_that_could_leave_the_content_directory() {
+        for invalid in [
+            "",
+            ".",
+            "..",
+            "../secret",
+            "..\\secret",
+            "a/b",
+            "/etc/passwd",
+            "a\\b",
+            "a\0b",
+            "a\nb",
+            "...",
+        ] {
+            assert!(Slug::parse(invalid).is_err(), "accepted {invalid:?}");
+        }
+    }
+}
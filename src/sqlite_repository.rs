@@ -0,0 +1,500 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use crate::Markdown;
+use crate::blog_repository::{
+    BlogRepository, ChangeHook, FileSystemBlogRepository, RepositoryError, sort_posts,
+};
+use crate::model::{parse_date_for_sorting, parse_time};
+use crate::slug::Slug;
+
+// `name` is the file name without extension the post was imported from and
+// doubles as its fallback slug, like for posts on disk. Dates are stored as
+// written in the front matter; `modified` is RFC 3339. `front_matter` holds
+// every key as JSON, including ones the engine does not interpret.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS posts (
+        name TEXT PRIMARY KEY,
+        slug TEXT,
+        title TEXT,
+        date_published TEXT,
+        body TEXT NOT NULL,
+        modified TEXT,
+        front_matter TEXT
+    );
+    CREATE INDEX IF NOT EXISTS posts_slug ON posts (slug);
+    CREATE TABLE IF NOT EXISTS pages (
+        name TEXT PRIMARY KEY,
+        body TEXT NOT NULL,
+        modified TEXT
+    );
+";
+
+const POST_COLUMNS: &str = "name, slug, title, date_published, body, modified, front_matter";
+
+// Reads posts and pages from a SQLite database, so other tools can manage
+// content by writing to it.
+pub(crate) struct SqliteBlogRepository {
+    connection: Arc<Mutex<Connection>>,
+    hooks: Arc<Mutex<Vec<ChangeHook>>>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub posts: usize,
+    pub pages: usize,
+}
+
+impl SqliteBlogRepository {
+    // Creates the database and its tables when missing.
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let connection = Connection::open(path)
+            .and_then(|connection| connection.execute_batch(SCHEMA).map(|_| connection))
+            .and_then(|connection| add_front_matter_column(&connection).map(|_| connection))
+            .map_err(|e| {
+                std::io::Error::other(format!(
+                    "SQLite database {} could not be opened: {e}",
+                    path.display()
+                ))
+            })?;
+        Ok(SqliteBlogRepository {
+            connection: Arc::new(Mutex::new(connection)),
+            hooks: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    // Checks every `interval` whether another connection wrote to the
+    // database and calls the change hooks if so, until the repository is
+    // dropped.
+    pub fn poll_for_changes(&self, interval: Duration) {
+        let connection = Arc::downgrade(&self.connection);
+        let hooks = self.hooks.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            let mut seen = None;
+            loop {
+                ticks.tick().await;
+                let connection = connection.clone();
+                let Ok(version) =
+                    tokio::task::spawn_blocking(move || data_version(&connection)).await
+                else {
+                    return;
+                };
+                let version = match version {
+                    Some(Ok(version)) => version,
+                    Some(Err(e)) => {
+                        eprintln!("Warning: Could not check SQLite for changes: {e}");
+                        continue;
+                    }
+                    None => return,
+                };
+                if seen.is_some_and(|seen| seen != version) {
+                    let hooks = hooks
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    for changed in hooks.iter() {
+                        changed();
+                    }
+                }
+                seen = Some(version);
+            }
+        });
+    }
+
+    // Loads `posts/` and `pages/` of a content directory, replacing entries
+    // with the same name.
+    pub fn import(&self, content_dir: &Path) -> Result<ImportSummary, RepositoryError> {
+        let source = FileSystemBlogRepository::new(content_dir.to_path_buf());
+        let posts = source.post_files()?;
+        let pages = source.page_files()?;
+
+        let mut connection = self.lock()?;
+        let transaction = connection.transaction().map_err(unexpected)?;
+        for post in &posts {
+            let parsed = Markdown::parse_front_matter(&post.content);
+            let front_matter = parsed.front_matter.unwrap_or_default();
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO posts
+                     (name, slug, title, date_published, body, modified, front_matter)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        post.slug(),
+                        front_matter.slug,
+                        front_matter.title,
+                        front_matter.publish_date,
+                        parsed.content,
+                        post.modified.map(|date| date.to_rfc3339()),
+                        parsed.front_matter_values.map(|values| values.to_string()),
+                    ],
+                )
+                .map_err(unexpected)?;
+        }
+        for page in &pages {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO pages (name, body, modified) VALUES (?1, ?2, ?3)",
+                    params![
+                        page.slug(),
+                        page.content,
+                        page.modified.map(|date| date.to_rfc3339())
+                    ],
+                )
+                .map_err(unexpected)?;
+        }
+        transaction.commit().map_err(unexpected)?;
+
+        Ok(ImportSummary {
+            posts: posts.len(),
+            pages: pages.len(),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, RepositoryError> {
+        self.connection
+            .lock()
+            .map_err(|_| RepositoryError::UnexpectedError)
+    }
+}
+
+// Changes whenever another connection commits. None once the repository is
+// dropped.
+fn data_version(connection: &Weak<Mutex<Connection>>) -> Option<rusqlite::Result<i64>> {
+    let connection = connection.upgrade()?;
+    let connection = connection
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    Some(connection.query_row("PRAGMA data_version", [], |row| row.get(0)))
+}
+
+// Databases created before the column existed get it on open.
+fn add_front_matter_column(connection: &Connection) -> rusqlite::Result<()> {
+    let exists = connection
+        .prepare("SELECT 1 FROM pragma_table_info('posts') WHERE name = 'front_matter'")?
+        .exists([])?;
+    if !exists {
+        connection.execute("ALTER TABLE posts ADD COLUMN front_matter TEXT", [])?;
+    }
+    Ok(())
+}
+
+fn post_from_row(row: &Row) -> rusqlite::Result<Markdown> {
+    let name: String = row.get("name")?;
+    let slug: Option<String> = row.get("slug")?;
+    let date_published: Option<String> = row.get("date_published")?;
+    let title: Option<String> = row.get("title")?;
+    // Rows written by other tools may only fill the columns.
+    let stored: Option<String> = row.get("front_matter")?;
+    let front_matter = stored
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| {
+            front_matter_of(title.as_deref(), slug.as_deref(), date_published.as_deref())
+        });
+    // Dates written in the front matter win over the column.
+    let time_of = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| front_matter.get(*key)?.as_str())
+            .and_then(parse_time)
+    };
+    let modified = time_of(&["modified", "dateModified"]);
+    let updated = time_of(&["updated"]);
+    Ok(Markdown {
+        title,
+        content: row.get("body")?,
+        slugs: slug.into_iter().chain(Some(name)).collect(),
+        publish_date: date_published.as_deref().and_then(parse_date_for_sorting),
+        modified: modified.or(parse_modified(row.get("modified")?)),
+        updated,
+        front_matter: Some(front_matter),
+    })
+}
+
+// The front matter columns under the keys used in markdown files.
+fn front_matter_of(
+    title: Option<&str>,
+    slug: Option<&str>,
+    date_published: Option<&str>,
+) -> serde_json::Value {
+    let columns = [
+        ("title", title),
+        ("slug", slug),
+        ("datePublished", date_published),
+    ];
+    let keys = columns
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?.into())));
+    serde_json::Value::Object(keys.collect())
+}
+
+fn parse_modified(modified: Option<String>) -> Option<DateTime<Utc>> {
+    modified
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn unexpected(e: rusqlite::Error) -> RepositoryError {
+    eprintln!("SQLite error: {}", e);
+    RepositoryError::UnexpectedError
+}
+
+impl BlogRepository for SqliteBlogRepository {
+    fn get_all_posts(&self) -> Result<Arc<[Markdown]>, RepositoryError> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare(&format!("SELECT {POST_COLUMNS} FROM posts"))
+            .map_err(unexpected)?;
+        let mut markdowns = statement
+            .query_map([], post_from_row)
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(unexpected)?;
+        sort_posts(&mut markdowns);
+        Ok(markdowns.into())
+    }
+
+    // A front matter slug wins over another post's name.
+    fn find_post_by_slug(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let connection = self.lock()?;
+        connection
+            .query_row(
+                &format!(
+                    "SELECT {POST_COLUMNS} FROM posts WHERE slug = ?1 OR name = ?1
+                     ORDER BY slug = ?1 DESC LIMIT 1"
+                ),
+                [slug.as_str()],
+                post_from_row,
+            )
+            .optional()
+            .map_err(unexpected)
+    }
+
+    fn get_page(&self, slug: &Slug) -> Result<Option<Markdown>, RepositoryError> {
+        let connection = self.lock()?;
+        connection
+            .query_row(
+                "SELECT body, modified FROM pages WHERE name = ?1",
+                [slug.as_str()],
+                |row| {
+                    Ok(Markdown {
+                        title: None,
+                        content: row.get("body")?,
+                        slugs: vec![slug.to_string()],
+                        publish_date: None,
+                        modified: parse_modified(row.get("modified")?),
+                        updated: None,
+                        front_matter: None,
+                    })
+                },
+            )
+            .optional()
+            .map_err(unexpected)
+    }
+
+    fn on_change(&self, changed: ChangeHook) {
+        self.hooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slug::slug;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn content_dir(posts: &[(&str, &str)], pages: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (subdir, files) in [("posts", posts), ("pages", pages)] {
+            fs::create_dir_all(dir.path().join(subdir)).unwrap();
+            for (name, content) in files {
+                fs::write(dir.path().join(subdir).join(name), content).unwrap();
+            }
+        }
+        dir
+    }
+
+    fn imported(posts: &[(&str, &str)], pages: &[(&str, &str)]) -> SqliteBlogRepository {
+        let content = content_dir(posts, pages);
+        let repo = SqliteBlogRepository::open(Path::new(":memory:")).unwrap();
+        repo.import(content.path()).unwrap();
+        repo
+    }
+
+    #[test]
+    fn imports_posts_with_front_matter_columns() {
+        let repo = imported(
+            &[(
+                "file-name.md",
+                "---\ntitle: Hello\nslug: hello\ndatePublished: 2023-01-01\n---\nBody\n",
+            )],
+            &[],
+        );
+
+        let post = repo.find_post_by_slug(&slug("hello")).unwrap().unwrap();
+
+        assert_eq!(post.title.as_deref(), Some("Hello"));
+        assert_eq!(post.slugs, vec!["hello", "file-name"]);
+        assert_eq!(post.publish_date, parse_date_for_sorting("2023-01-01"));
+        assert_eq!(post.content.trim(), "Body");
+        assert!(
+            repo.find_post_by_slug(&slug("file-name"))
+                .unwrap()
+                .is_some()
+        );
+        assert!(repo.find_post_by_slug(&slug("other")).unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_every_front_matter_key() {
+        let repo = imported(
+            &[(
+                "math.md",
+                "---\ntitle: Math\nmath: true\ntags: [a, b]\n---\n$x$\n",
+            )],
+            &[],
+        );
+
+        let post = repo.find_post_by_slug(&slug("math")).unwrap().unwrap();
+
+        assert!(post.front_matter_flag("math"));
+        assert_eq!(
+            post.front_matter.unwrap(),
+            serde_json::json!({"title": "Math", "math": true, "tags": ["a", "b"]})
+        );
+    }
+
+    #[test]
+    fn reads_modified_and_updated_dates_from_the_front_matter() {
+        let repo = imported(
+            &[(
+                "a.md",
+                "---\ntitle: A\ndateModified: 2023-06-01\nupdated: 2023-07-01T10:00:00+02:00\n---\n",
+            )],
+            &[],
+        );
+
+        let post = repo.find_post_by_slug(&slug("a")).unwrap().unwrap();
+
+        assert_eq!(post.modified, parse_time("2023-06-01"));
+        assert_eq!(
+            post.updated.unwrap().to_rfc3339(),
+            "2023-07-01T08:00:00+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_change_hooks_after_writes_by_other_connections() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blog.db");
+        let repo = SqliteBlogRepository::open(&path).unwrap();
+        let changes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        repo.on_change({
+            let changes = changes.clone();
+            Arc::new(move || {
+                changes.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            })
+        });
+        repo.poll_for_changes(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(changes.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        Connection::open(&path)
+            .unwrap()
+            .execute(
+                "INSERT INTO posts (name, title, body) VALUES ('b', 'External', '')",
+                [],
+            )
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(changes.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn adds_the_front_matter_column_to_older_databases() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("blog.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE posts (name TEXT PRIMARY KEY, slug TEXT, title TEXT,
+                 date_published TEXT, body TEXT NOT NULL, modified TEXT);
+                 INSERT INTO posts (name, title, body) VALUES ('old', 'Old', '');",
+            )
+            .unwrap();
+
+        let repo = SqliteBlogRepository::open(&path).unwrap();
+        let post = repo.find_post_by_slug(&slug("old")).unwrap().unwrap();
+
+        assert_eq!(
+            post.front_matter.unwrap(),
+            serde_json::json!({"title": "Old"})
+        );
+    }
+
+    #[test]
+    fn sorts_posts_and_serves_pages() {
+        let repo = imported(
+            &[
+                (
+                    "old.md",
+                    "---\ntitle: Old\ndatePublished: 2020-01-01\n---\n",
+                ),
+                (
+                    "new.md",
+                    "---\ntitle: New\ndatePublished: 2022-01-01\n---\n",
+                ),
+            ],
+            &[("about.md", "About me")],
+        );
+
+        let titles: Vec<String> = repo
+            .get_all_posts()
+            .unwrap()
+            .iter()
+            .filter_map(|post| post.title.clone())
+            .collect();
+
+        assert_eq!(titles, vec!["New", "Old"]);
+        assert_eq!(
+            repo.get_page(&slug("about")).unwrap().unwrap().content,
+            "About me"
+        );
+    }
+
+    #[test]
+    fn reimport_replaces_posts_and_sees_external_writes() {
+        let content = content_dir(&[("a.md", "---\ntitle: Before\n---\n")], &[]);
+        let repo = SqliteBlogRepository::open(&content.path().join("blog.db")).unwrap();
+        repo.import(content.path()).unwrap();
+
+        fs::write(
+            content.path().join("posts/a.md"),
+            "---\ntitle: After\n---\n",
+        )
+        .unwrap();
+        let summary = repo.import(content.path()).unwrap();
+        Connection::open(content.path().join("blog.db"))
+            .unwrap()
+            .execute(
+                "INSERT INTO posts (name, title, body) VALUES ('b', 'External', '')",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(summary, ImportSummary { posts: 1, pages: 0 });
+        let titles: Vec<String> = repo
+            .get_all_posts()
+            .unwrap()
+            .iter()
+            .filter_map(|post| post.title.clone())
+            .collect();
+        assert_eq!(titles, vec!["After", "External"]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::config::BlogConfig;
+use crate::directories::BlogDir;
+use crate::model::{BlogPost, DiffLine, RevisionEntry};
+use crate::renderer;
+use crate::theme::ThemeDirs;
+
+// Every template the engine renders by name.
+pub(crate) const REQUIRED_TEMPLATES: &[&str] = &[
+    "index.html",
+    "post.html",
+    "page.html",
+    "history.html",
+    "404.html",
+    "500.html",
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateIssueKind {
+    // The templates could not be compiled; parse errors are reported for
+    // the whole set, as Tera only compiles templates together.
+    Parse,
+    Missing,
+    // The dry-run render with a sample context failed, for example on an
+    // undefined variable.
+    Render,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TemplateIssue {
+    pub kind: TemplateIssueKind,
+    pub template: Option<String>,
+    pub message: String,
+}
+
+impl TemplateIssue {
+    pub(crate) fn parse(error: &tera::Error) -> Self {
+        TemplateIssue {
+            kind: TemplateIssueKind::Parse,
+            template: None,
+            message: error_chain(error),
+        }
+    }
+
+    // One line of `key=value` pairs, so log tooling can pick out the fields.
+    pub(crate) fn warn(&self) {
+        log::warn!(
+            "template={} kind={} message={:?}",
+            self.template.as_deref().unwrap_or("-"),
+            self.kind,
+            self.message
+        );
+    }
+}
+
+impl fmt::Display for TemplateIssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TemplateIssueKind::Parse => "parse",
+            TemplateIssueKind::Missing => "missing",
+            TemplateIssueKind::Render => "render",
+        })
+    }
+}
+
+impl fmt::Display for TemplateIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.template {
+            Some(template) => write!(f, "template {}: {}", template, self.message),
+            None => write!(f, "templates: {}", self.message),
+        }
+    }
+}
+
+// Loads the templates of the blog directory the way the server does and
+// reports everything that would make a page fail to render.
+pub fn validate_templates(blog_dir: &BlogDir, config: &BlogConfig) -> Vec<TemplateIssue> {
+    match ThemeDirs::new(blog_dir, config).templates() {
+        Ok(tera) => check_templates(&tera, config),
+        Err(e) => vec![TemplateIssue::parse(&e)],
+    }
+}
+
+// Each template is rendered twice when `dry_run` is set: with only the
+// variables that are always there, and with every optional one as well, so
+// both an unguarded optional variable and a typo in a branch show up.
+pub(crate) fn check_templates(tera: &Tera, config: &BlogConfig) -> Vec<TemplateIssue> {
+    let names: BTreeSet<&str> = tera.get_template_names().collect();
+    let mut issues = Vec::new();
+    for template in REQUIRED_TEMPLATES {
+        if !names.contains(template) {
+            issues.push(TemplateIssue {
+                kind: TemplateIssueKind::Missing,
+                template: Some(template.to_string()),
+                message: "template not found".to_string(),
+            });
+            continue;
+        }
+        if !config.templates.dry_run {
+            continue;
+        }
+        let failure = sample_contexts(template, config)
+            .iter()
+            .find_map(|context| tera.render(template, context).err());
+        if let Some(e) = failure {
+            issues.push(TemplateIssue {
+                kind: TemplateIssueKind::Render,
+                template: Some(template.to_string()),
+                message: error_chain(&e),
+            });
+        }
+    }
+    issues
+}
+
+// The variables a template gets from the renderer, without and with the
+// optional ones.
+fn sample_contexts(template: &str, config: &BlogConfig) -> [Context; 2] {
+    let mut always = renderer::base_context(config, "/sample");
+    let mut optional = Context::new();
+    match template {
+        "index.html" => {
+            always.insert("posts", &Vec::<BlogPost>::new());
+            optional.insert(
+                "posts",
+                &[BlogPost {
+                    title: "Sample".to_string(),
+                    publish_date: Some("1 January 2024".to_string()),
+                    slug: "sample".to_string(),
+                }],
+            );
+        }
+        "post.html" => {
+            always.insert("content", "<p>Sample</p>");
+            always.insert("slug", "sample");
+            optional.insert("title", "Sample");
+            optional.insert("date", "1 January 2024");
+            optional.insert("updated", "2 January 2024");
+        }
+        "page.html" => {
+            always.insert("content", "<p>Sample</p>");
+            optional.insert("title", "Sample");
+        }
+        "history.html" => {
+            always.insert("slug", "sample");
+            always.insert("revisions", &Vec::<RevisionEntry>::new());
+            optional.insert("title", "Sample");
+            optional.insert(
+                "revisions",
+                &[RevisionEntry {
+                    hash: "0123456789abcdef".to_string(),
+                    short_hash: "0123456".to_string(),
+                    message: "Sample".to_string(),
+                    date: "1 January 2024".to_string(),
+                    previous_hash: Some("fedcba9876543210".to_string()),
+                }],
+            );
+            optional.insert(
+                "diff",
+                &[DiffLine {
+                    kind: "added",
+                    text: "Sample".to_string(),
+                }],
+            );
+            optional.insert("from", "fedcba9");
+            optional.insert("to", "0123456");
+        }
+        "404.html" => always.insert("status", &404),
+        "500.html" => always.insert("status", &500),
+        _ => {}
+    }
+    let mut full = always.clone();
+    full.extend(optional);
+    [always, full]
+}
+
+// Tera puts the useful part, like the undefined variable, in the sources.
+fn error_chain(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin;
+
+    fn issues(templates: &[(&str, &str)]) -> Vec<TemplateIssue> {
+        let mut tera = builtin::templates();
+        tera.add_raw_templates(templates.to_vec()).unwrap();
+        check_templates(&tera, &BlogConfig::default())
+    }
+
+    #[test]
+    fn built_in_templates_pass() {
+        assert!(issues(&[]).is_empty());
+    }
+
+    #[test]
+    fn reports_missing_templates() {
+        let issues = check_templates(&Tera::default(), &BlogConfig::default());
+
+        assert_eq!(issues.len(), REQUIRED_TEMPLATES.len());
+        assert!(
+            issues
+                .iter()
+                .all(|issue| issue.kind == TemplateIssueKind::Missing)
+        );
+    }
+
+    #[test]
+    fn dry_run_finds_undefined_variables() {
+        let issues = issues(&[
+            ("page.html", "{{ title }}"),
+            ("post.html", "{% if date %}{{ dat }}{% endif %}"),
+        ]);
+
+        let templates: Vec<_> = issues
+            .iter()
+            .filter_map(|i| i.template.as_deref())
+            .collect();
+        assert_eq!(templates, vec!["post.html", "page.html"]);
+        assert!(issues[1].message.contains("title"), "{}", issues[1].message);
+
+        let mut config = BlogConfig::default();
+        config.templates.dry_run = false;
+        let mut tera = builtin::templates();
+        tera.add_raw_template("page.html", "{{ title }}").unwrap();
+        assert!(check_templates(&tera, &config).is_empty());
+    }
+}
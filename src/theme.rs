@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tera::Tera;
+
+use crate::builtin;
+use crate::config::{BlogConfig, DEFAULT_THEME};
+use crate::directories::BlogDir;
+
+// Contents of `theme.yaml` at the root of a theme directory.
+#[derive(Deserialize, Debug)]
+pub struct ThemeMetadata {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // Config keys the templates rely on; nested keys are written with dots,
+    // like `extra.author`.
+    #[serde(default)]
+    pub required_config: Vec<String>,
+}
+
+// A theme directory with `templates/` and `static/` next to `theme.yaml`.
+#[derive(Debug)]
+pub struct Theme {
+    pub metadata: ThemeMetadata,
+    pub dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+    NotFound(PathBuf),
+    InvalidMetadata(PathBuf, String),
+    MissingConfig { theme: String, keys: Vec<String> },
+}
+
+impl Theme {
+    // Loads the theme selected by `config.theme` and checks that the config
+    // has every key the theme requires. The default theme is built in and
+    // does not need a directory.
+    pub fn load(blog_dir: &BlogDir, config: &BlogConfig) -> Result<Theme, ThemeError> {
+        let dir = blog_dir.themes_dir().join(&config.theme);
+        if !dir.is_dir() {
+            if config.theme != DEFAULT_THEME {
+                return Err(ThemeError::NotFound(dir));
+            }
+            let metadata = builtin::metadata();
+            metadata.check_config(config)?;
+            return Ok(Theme { metadata, dir });
+        }
+        let metadata_file = dir.join("theme.yaml");
+        let metadata = std::fs::read_to_string(&metadata_file)
+            .map_err(|e| e.to_string())
+            .and_then(|yaml| ThemeMetadata::parse(&yaml))
+            .map_err(|e| ThemeError::InvalidMetadata(metadata_file, e))?;
+        metadata.check_config(config)?;
+        Ok(Theme { metadata, dir })
+    }
+}
+
+impl ThemeMetadata {
+    pub fn parse(yaml: &str) -> Result<Self, String> {
+        let metadata: ThemeMetadata = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+        if metadata.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        Ok(metadata)
+    }
+
+    pub fn check_config(&self, config: &BlogConfig) -> Result<(), ThemeError> {
+        let values = serde_yaml::to_value(config).unwrap_or_default();
+        let missing: Vec<String> = self
+            .required_config
+            .iter()
+            .filter(|key| !has_key(&values, key))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ThemeError::MissingConfig {
+                theme: self.name.clone(),
+                keys: missing,
+            })
+        }
+    }
+}
+
+fn has_key(values: &serde_yaml::Value, key: &str) -> bool {
+    let value = key
+        .split('.')
+        .try_fold(values, |value, part| value.get(part));
+    value.is_some_and(|value| !value.is_null())
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::NotFound(dir) => write!(f, "theme directory {} not found", dir.display()),
+            ThemeError::InvalidMetadata(file, e) => {
+                write!(f, "theme metadata {} is invalid: {}", file.display(), e)
+            }
+            ThemeError::MissingConfig { theme, keys } => write!(
+                f,
+                "theme '{}' requires config keys that are not set: {}",
+                theme,
+                keys.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+// Lookup chain for templates and static files: files in the blog directory
+// override the theme's files of the same name, one by one.
+pub(crate) struct ThemeDirs {
+    site_templates: PathBuf,
+    site_static: PathBuf,
+    theme: PathBuf,
+}
+
+impl ThemeDirs {
+    pub fn new(blog_dir: &BlogDir, config: &BlogConfig) -> Self {
+        ThemeDirs {
+            site_templates: blog_dir.templates_dir(),
+            site_static: blog_dir.static_dir(),
+            theme: blog_dir.themes_dir().join(&config.theme),
+        }
+    }
+
+    // Theme templates are also available as `theme/<name>`, so a site
+    // template can extend the one it replaces. Templates neither directory
+    // has come from the built-in default theme.
+    pub fn templates(&self) -> Result<Tera, tera::Error> {
+        let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for (name, path) in html_files(&self.theme.join("templates")) {
+            files.insert(format!("theme/{name}"), path.clone());
+            files.insert(name, path);
+        }
+        files.extend(html_files(&self.site_templates));
+
+        let mut tera = builtin::templates();
+        tera.add_template_files(files.iter().map(|(name, path)| (path, Some(name))))?;
+        Ok(tera)
+    }
+
+    // Highest priority first.
+    pub fn static_dirs(&self) -> [PathBuf; 2] {
+        [self.site_static.clone(), self.theme.join("static")]
+    }
+
+    pub fn has_static_file(&self, asset: &str) -> bool {
+        self.static_dirs()
+            .iter()
+            .any(|dir| dir.join(asset).is_file())
+            || builtin::has_static_file(asset)
+    }
+}
+
+// Template files below `dir` by their name relative to it.
+fn html_files(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "html")
+                && let Ok(relative) = path.strip_prefix(dir)
+            {
+                let name = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((name, path));
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn blog_dir(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, content) in files {
+            let file = dir.path().join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        dir
+    }
+
+    fn render(tera: &Tera, name: &str) -> String {
+        tera.render(name, &tera::Context::new()).unwrap()
+    }
+
+    #[test]
+    fn site_templates_override_theme_templates_by_name() {
+        let dir = blog_dir(&[
+            (
+                "themes/default/templates/base.html",
+                "theme base {% block body %}{% endblock %}",
+            ),
+            (
+                "themes/default/templates/post.html",
+                "{% extends \"base.html\" %}{% block body %}theme post{% endblock %}",
+            ),
+            (
+                "templates/post.html",
+                "{% extends \"theme/post.html\" %}{% block body %}site post{% endblock %}",
+            ),
+        ]);
+        let dirs = ThemeDirs::new(&BlogDir(dir.path().into()), &BlogConfig::default());
+
+        let tera = dirs.templates().unwrap();
+
+        assert_eq!(render(&tera, "post.html"), "theme base site post");
+        assert_eq!(render(&tera, "theme/post.html"), "theme base theme post");
+    }
+
+    #[test]
+    fn static_files_are_looked_up_in_site_theme_and_built_in_files() {
+        let dir = blog_dir(&[
+            ("themes/default/static/img/theme.png", "theme"),
+            ("static/img/logo.png", "site"),
+        ]);
+        let dirs = ThemeDirs::new(&BlogDir(dir.path().into()), &BlogConfig::default());
+
+        assert!(dirs.has_static_file("img/logo.png"));
+        assert!(dirs.has_static_file("img/theme.png"));
+        assert!(dirs.has_static_file("css/main.css"));
+        assert!(!dirs.has_static_file("missing.css"));
+    }
+
+    #[test]
+    fn loading_theme_checks_metadata_and_required_config() {
+        let dir = blog_dir(&[
+            (
+                "themes/default/theme.yaml",
+                "name: Default\nrequired_config: [site_title, extra.author]\n",
+            ),
+            ("themes/broken/theme.yaml", "name: ''\n"),
+        ]);
+        let blog_dir = BlogDir(dir.path().into());
+        let mut config = BlogConfig::default();
+
+        match Theme::load(&blog_dir, &config) {
+            Err(ThemeError::MissingConfig { keys, .. }) => assert_eq!(keys, vec!["extra.author"]),
+            other => panic!("expected missing config, got {other:?}"),
+        }
+
+        config.extra.insert("author".to_string(), "Me".into());
+        assert_eq!(
+            Theme::load(&blog_dir, &config).unwrap().metadata.name,
+            "Default"
+        );
+
+        config.theme = "broken".to_string();
+        assert!(matches!(
+            Theme::load(&blog_dir, &config),
+            Err(ThemeError::InvalidMetadata(..))
+        ));
+        config.theme = "missing".to_string();
+        assert!(matches!(
+            Theme::load(&blog_dir, &config),
+            Err(ThemeError::NotFound(_))
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use rstest::rstest;
 
+use blog_engine::InMemoryBlogRepository;
+
 use crate::specification_support::BlogServer;
 
 #[tokio::test]
@@ -11,6 +13,17 @@ async fn health_endpoint_should_return_200() {
         .await;
 }
 
+#[tokio::test]
+async fn cache_stats_endpoint_should_report_counters() {
+    BlogServer::new()
+        .get("/health/cache")
+        .expect_status_code(200)
+        .expect_body_contains("\"hits\"")
+        .expect_body_contains("\"evictions\"")
+        .execute()
+        .await;
+}
+
 #[tokio::test]
 async fn post_should_be_accessible_via_filename() {
     let post_content = ""; // content not relevant for test
@@ -250,12 +263,566 @@ Content here.";
         .await;
 }
 
+#[tokio::test]
+#[rstest]
+async fn all_should_send_validators(#[values("/", "/post", "/p/page")] path: &str) {
+    BlogServer::new()
+        .add_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .add_file("pages/page.md", "# Page")
+        .get(path)
+        .expect_status_code(200)
+        .expect_header_present("etag")
+        .expect_header_present("last-modified")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_return_304_when_etag_matches() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .with_header("if-none-match", "*")
+        .expect_status_code(304)
+        .expect_header_present("etag")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_return_200_when_etag_differs() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .with_header("if-none-match", "\"stale\"")
+        .expect_status_code(200)
+        .expect_body_contains("Post")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_return_304_when_not_modified_since() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .with_header("if-modified-since", "Fri, 01 Jan 2100 00:00:00 GMT")
+        .expect_status_code(304)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+#[rstest]
+#[case("gzip", "gzip")]
+#[case("gzip, br", "br")]
+#[case("br;q=0.5, gzip", "gzip")]
+async fn post_should_be_compressed_when_accepted(
+    #[case] accept_encoding: &str,
+    #[case] expected_encoding: &str,
+) {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .with_header("accept-encoding", accept_encoding)
+        .expect_status_code(200)
+        .expect_header("content-encoding", expected_encoding)
+        .expect_header("vary", "accept, accept-encoding")
+        .execute()
+        .await;
+}
+
+fn post_with_two_revisions() -> BlogServer {
+    BlogServer::new()
+        .add_commit(
+            "Write hello",
+            &[("posts/hello.md", "---\ntitle: Hello\n---\nFirst draft\n")],
+        )
+        .add_commit(
+            "Fix wording in hello",
+            &[("posts/hello.md", "---\ntitle: Hello\n---\nFinal text\n")],
+        )
+}
+
+#[tokio::test]
+async fn history_should_list_commits_of_post_newest_first() {
+    post_with_two_revisions()
+        .get("/hello/history")
+        .expect_status_code(200)
+        .expect_contains_in_order(&["Fix wording in hello", "Write hello"])
+        .expect_body_contains("January 02, 2024")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_show_updated_date_from_latest_commit() {
+    post_with_two_revisions()
+        .get("/hello")
+        .expect_body_contains("Updated on January 02, 2024")
+        .expect_body_contains("/hello/history")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_not_link_history_when_post_not_in_git() {
+    BlogServer::with_file("posts/hello.md", "---\nupdated: 2024-01-02\n---\nHi")
+        .get("/hello")
+        .expect_body_contains("Updated on January 02, 2024")
+        .expect_not_contains("/hello/history")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn history_should_render_diff_between_revisions() {
+    post_with_two_revisions()
+        .get("/hello/history?from={commit:0}&to={commit:1}")
+        .expect_status_code(200)
+        .expect_body_contains("-First draft")
+        .expect_body_contains("+Final text")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn history_should_return_404_for_refs_that_are_not_post_revisions() {
+    post_with_two_revisions()
+        .get("/hello/history?from=HEAD~1&to=HEAD")
+        .expect_status_code(404)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn history_should_return_404_when_post_not_in_git() {
+    BlogServer::with_file("posts/hello.md", "")
+        .get("/hello/history")
+        .expect_status_code(404)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn in_memory_post_should_be_rendered() {
+    let repository = InMemoryBlogRepository::new()
+        .with_post("hello", "---\ntitle: Hello World\n---\nFrom memory\n");
+
+    BlogServer::with_repository(repository)
+        .get("/hello")
+        .expect_status_code(200)
+        .expect_body_contains("Hello World")
+        .expect_body_contains("From memory")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn in_memory_index_should_display_posts_in_order() {
+    let repository = InMemoryBlogRepository::new()
+        .with_post(
+            "old",
+            "---\ntitle: Old Post\ndatePublished: 2020-01-01\n---\n",
+        )
+        .with_post(
+            "new",
+            "---\ntitle: New Post\ndatePublished: 2022-01-01\n---\n",
+        );
+
+    BlogServer::with_repository(repository)
+        .get("/")
+        .expect_contains_in_order(&["New Post", "Old Post"])
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn in_memory_post_added_while_serving_should_appear_on_index() {
+    let repository = InMemoryBlogRepository::new().with_post("old", "---\ntitle: Old Post\n---\n");
+    let handle = repository.clone();
+
+    BlogServer::with_repository(repository)
+        .after_request("/", move || {
+            handle.add_post("new", "---\ntitle: Added Post\n---\n");
+        })
+        .get("/")
+        .expect_body_contains("Added Post")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn in_memory_page_should_be_accessible_via_p_prefix() {
+    let repository = InMemoryBlogRepository::new().with_page("about", "About this blog");
+
+    BlogServer::with_repository(repository)
+        .get("/p/about")
+        .expect_status_code(200)
+        .expect_body_contains("About this blog")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn in_memory_repository_should_return_404_for_unknown_post() {
+    BlogServer::with_repository(InMemoryBlogRepository::new())
+        .get("/missing")
+        .expect_status_code(404)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn shared_content_dir_should_add_posts_without_overriding_own() {
+    BlogServer::new()
+        .add_file("posts/hello.md", "---\ntitle: Own Hello\n---\n")
+        .add_file("shared/posts/hello.md", "---\ntitle: Shared Hello\n---\n")
+        .add_file("shared/posts/company.md", "---\ntitle: Company News\n---\n")
+        .with_config(
+            "site_title: Blog\nsite_description: Blog\nstorage:\n  content_dirs: [shared]\n",
+        )
+        .get("/")
+        .expect_body_contains("Own Hello")
+        .expect_body_contains("Company News")
+        .expect_not_contains("Shared Hello")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn api_should_list_posts_with_pagination() {
+    BlogServer::with_file(
+        "posts/new.md",
+        "---\ntitle: New\ndatePublished: 2023-02-01\n---\n",
+    )
+    .add_file(
+        "posts/old.md",
+        "---\ntitle: Old\ndatePublished: 2023-01-01\n---\n",
+    )
+    .get("/api/posts?per_page=1&page=2")
+    .expect_status_code(200)
+    .expect_header("content-type", "application/json")
+    .expect_body_contains("\"title\":\"Old\"")
+    .expect_body_contains("\"total\":2")
+    .expect_body_contains("\"total_pages\":2")
+    .expect_not_contains("\"title\":\"New\"")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+async fn api_should_return_post_with_front_matter_markdown_and_html() {
+    BlogServer::with_file(
+        "posts/file.md",
+        "---\ntitle: Hello\nslug: hello\ntags: [rust]\n---\n**Bold**\n",
+    )
+    .get("/api/posts/hello")
+    .expect_status_code(200)
+    .expect_body_contains("\"slugs\":[\"hello\",\"file\"]")
+    .expect_body_contains("\"tags\":[\"rust\"]")
+    .expect_body_contains("\"markdown\":\"**Bold**\"")
+    .expect_body_contains("<strong>Bold</strong>")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+#[rstest]
+async fn api_should_return_404_for_unknown_content(
+    #[values("/api/posts/missing", "/api/pages/missing")] path: &str,
+) {
+    BlogServer::new()
+        .get(path)
+        .expect_status_code(404)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+#[rstest]
+#[case("application/json", "application/json")]
+#[case("text/html,application/json;q=0.9", "text/html; charset=utf-8")]
+async fn post_should_negotiate_json_via_accept_header(
+    #[case] accept: &str,
+    #[case] expected_content_type: &str,
+) {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .with_header("accept", accept)
+        .expect_status_code(200)
+        .expect_header("content-type", expected_content_type)
+        .expect_header("vary", "accept, accept-encoding")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+#[rstest]
+#[case("/p/..%2Fsecret")]
+#[case("/p/..%2F..%2Fsecret")]
+#[case("/p/..%5Csecret")]
+#[case("/p/%2E%2E%2Fsecret")]
+#[case("/p/secret%00")]
+#[case("/..%2Fsecret")]
+#[case("/..%2Fsecret/history")]
+#[case("/api/pages/..%2Fsecret")]
+#[case("/api/posts/..%2Fsecret")]
+async fn slugs_escaping_content_dir_should_be_rejected(#[case] path: &str) {
+    BlogServer::with_file("secret.md", "Top secret")
+        .add_file("pages/about.md", "About")
+        .get(path)
+        .expect_status_code(400)
+        .expect_not_contains("Top secret")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn site_template_should_override_theme_template() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .add_blog_file(
+            "templates/partials/footer.html",
+            "<footer>Site footer</footer>",
+        )
+        .get("/post")
+        .expect_status_code(200)
+        .expect_body_contains("Site footer")
+        .expect_body_contains("site-header")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+#[rstest]
+async fn static_files_should_fall_back_to_theme(
+    #[values("/static/css/site.css", "/static/css/main.css")] path: &str,
+) {
+    BlogServer::new()
+        .add_blog_file("static/css/site.css", "body {}")
+        .get(path)
+        .expect_status_code(200)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn configured_theme_should_be_used() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .add_blog_file("themes/plain/theme.yaml", "name: Plain\n")
+        .add_blog_file("themes/plain/templates/post.html", "Plain {{ title }}")
+        .with_config("site_title: Blog\nsite_description: Blog\ntheme: plain\n")
+        .get("/post")
+        .expect_status_code(200)
+        .expect_body_contains("Plain Post")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn post_should_render_without_templates_in_blog_dir() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Built in\n---\nHello")
+        .with_empty_blog_dir()
+        .get("/post")
+        .expect_status_code(200)
+        .expect_body_contains("Built in")
+        .expect_body_contains("<p>Hello</p>")
+        .expect_body_contains("/static/css/main.css")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn built_in_stylesheet_should_be_served_without_static_dir() {
+    BlogServer::new()
+        .with_empty_blog_dir()
+        .get("/static/css/main.css")
+        .expect_status_code(200)
+        .expect_header("content-type", "text/css")
+        .execute()
+        .await;
+}
+
+#[rstest]
+#[tokio::test]
+async fn unknown_html_url_should_show_not_found_page(
+    #[values("/missing-post", "/p/missing-page", "/a/b/c")] path: &str,
+) {
+    BlogServer::new()
+        .with_empty_blog_dir()
+        .get(path)
+        .expect_status_code(404)
+        .expect_body_contains("Page not found")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn api_should_not_show_not_found_page() {
+    BlogServer::new()
+        .get("/api/posts/missing")
+        .expect_status_code(404)
+        .expect_not_contains("Page not found")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn code_should_be_highlighted_with_classes_when_configured() {
+    BlogServer::with_file("posts/code.md", "```rust\nfn main() {}\n```\n")
+        .with_config("site_title: Blog\nsite_description: Blog\nhighlighting:\n  mode: classes\n")
+        .get("/code")
+        .expect_status_code(200)
+        .expect_body_contains("<pre class=\"hl-code\" data-lang=\"rust\">")
+        .expect_body_contains("href=\"/_highlight/theme.css\"")
+        .expect_not_contains("style=\"color")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn code_block_should_render_title_line_numbers_and_highlighted_lines() {
+    BlogServer::with_file(
+        "posts/code.md",
+        "```rust title=\"main.rs\" {2} linenos\nfn main() {\n    run();\n}\n```\n",
+    )
+    .get("/code")
+    .expect_status_code(200)
+    .expect_body_contains("<figcaption class=\"code-title\">main.rs</figcaption>")
+    .expect_body_contains("data-lang=\"rust\"><code class=\"language-rust\">")
+    .expect_body_contains("<span class=\"line highlighted\"><span class=\"line-number\"")
+    .expect_not_contains("<code><pre")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+async fn highlight_stylesheet_should_include_dark_theme() {
+    BlogServer::new()
+        .with_config(
+            "site_title: Blog\nsite_description: Blog\nhighlighting:\n  mode: classes\n  theme: InspiredGitHub\n  dark_theme: base16-ocean.dark\n",
+        )
+        .get("/_highlight/theme.css")
+        .expect_status_code(200)
+        .expect_header("content-type", "text/css")
+        .expect_body_contains(".hl-code {")
+        .expect_body_contains("@media (prefers-color-scheme: dark)")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn highlight_stylesheet_should_not_exist_in_inline_mode() {
+    BlogServer::new()
+        .get("/_highlight/theme.css")
+        .expect_status_code(404)
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn highlight_stylesheet_should_not_shadow_static_files_of_the_site() {
+    BlogServer::new()
+        .with_config("site_title: Blog\nsite_description: Blog\nhighlighting:\n  mode: classes\n")
+        .add_blog_file("static/highlight.css", "/* site */")
+        .get("/static/highlight.css")
+        .expect_status_code(200)
+        .expect_body_contains("/* site */")
+        .execute()
+        .await;
+}
+
+#[tokio::test]
+async fn callouts_should_render_as_asides() {
+    BlogServer::with_file(
+        "posts/callouts.md",
+        "> [!TIP] Faster builds\n> Use the cache.\n\n:::warning\nSlow\n:::\n\n> [!OTHER]\n> Quote\n",
+    )
+    .get("/callouts")
+    .expect_status_code(200)
+    .expect_body_contains("<aside class=\"callout callout-tip\" role=\"note\">")
+    .expect_body_contains("</span> Faster builds</p>")
+    .expect_body_contains("<aside class=\"callout callout-warning\" role=\"note\">")
+    .expect_body_contains("<blockquote>\n<p>[!OTHER]")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+async fn diagram_code_block_should_render_to_inline_svg() {
+    BlogServer::with_file(
+        "posts/diagram.md",
+        "```svgbob title=\"Request flow\"\n+--------+     +-----+\n| client |---->| app |\n+--------+     +-----+\n```\n",
+    )
+    .get("/diagram")
+    .expect_status_code(200)
+    .expect_body_contains("<figure class=\"diagram\"><svg xmlns=\"http://www.w3.org/2000/svg\"")
+    .expect_body_contains(">client</text>")
+    .expect_body_contains("<figcaption>Request flow</figcaption>")
+    .expect_not_contains("<pre")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+async fn math_should_render_to_mathml_in_posts_that_enable_it() {
+    BlogServer::with_file(
+        "posts/math.md",
+        "---\ntitle: Math\nmath: true\n---\nEnergy $E = mc^2$, but not `$x$` or [$y$](https://example.com/$z$).\n\n$$\n\\frac{1}{\\nope}\n$$\n",
+    )
+    .get("/math")
+    .expect_status_code(200)
+    .expect_body_contains("<math display=\"inline\" xmlns=\"http://www.w3.org/1998/Math/MathML\">")
+    .expect_body_contains("<msup><mi>c</mi><mn>2</mn></msup>")
+    .expect_body_contains("<code>$x$</code>")
+    .expect_body_contains("href=\"https://example.com/$z$\"")
+    .expect_body_contains("<code class=\"math-error\" title=\"unknown primitive command found\">")
+    .execute()
+    .await;
+}
+
+#[tokio::test]
+async fn math_should_stay_text_without_the_front_matter_switch() {
+    BlogServer::with_file(
+        "posts/prices.md",
+        "---\ntitle: Prices\n---\nFrom $a$ to $b$\n",
+    )
+    .get("/prices")
+    .expect_status_code(200)
+    .expect_body_contains("From $a$ to $b$")
+    .expect_not_contains("<math")
+    .execute()
+    .await;
+}
+
+#[test]
+fn sqlite_backend_should_fail_startup_when_database_cannot_be_opened() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut config = blog_engine::BlogConfig::default();
+    config.storage.backend = blog_engine::Backend::Sqlite;
+    config.storage.sqlite_path = "missing/blog.db".into();
+
+    assert!(blog_engine::create_app_with_config(dir.path(), dir.path(), config).is_err());
+}
+
+#[tokio::test]
+async fn post_should_not_be_compressed_without_accept_encoding() {
+    BlogServer::with_file("posts/post.md", "---\ntitle: Post\n---\n")
+        .get("/post")
+        .expect_header_absent("content-encoding")
+        .expect_body_contains("Post")
+        .execute()
+        .await;
+}
+
 mod specification_support {
     use axum::Router;
     use axum::serve;
+    use blog_engine::BlogConfig;
     use blog_engine::BlogDir;
     use blog_engine::ContentDir;
+    use blog_engine::InMemoryBlogRepository;
+    use blog_engine::create_app_from_git;
     use blog_engine::create_app_with_dirs;
+    use blog_engine::create_app_with_repository;
+    use git2::{Repository, Signature, Time};
+    use std::collections::HashMap;
     use std::fs;
     use std::net::SocketAddr;
     use tempfile::TempDir;
@@ -265,6 +832,11 @@ mod specification_support {
     pub struct BlogServer {
         content_on_server: Vec<FileOnServer>,
         config: Option<String>,
+        commits: Vec<CommitOnServer>,
+        repository: Option<InMemoryBlogRepository>,
+        blog_files: Vec<FileOnServer>,
+        empty_blog_dir: bool,
+        earlier_request: Option<(String, Box<dyn FnOnce() + Send>)>,
     }
 
     impl BlogServer {
@@ -272,6 +844,11 @@ mod specification_support {
             BlogServer {
                 content_on_server: Vec::new(),
                 config: None,
+                commits: Vec::new(),
+                repository: None,
+                blog_files: Vec::new(),
+                empty_blog_dir: false,
+                earlier_request: None,
             }
         }
 
@@ -279,6 +856,7 @@ mod specification_support {
             Obtained {
                 server: self, // BlogServer now directly owns itself in Obtained
                 path: path.to_string(),
+                headers: Vec::new(),
                 assertions: Vec::new(),
             }
         }
@@ -290,6 +868,11 @@ mod specification_support {
                     content: content.to_string(),
                 }],
                 config: None,
+                commits: Vec::new(),
+                repository: None,
+                blog_files: Vec::new(),
+                empty_blog_dir: false,
+                earlier_request: None,
             }
         }
 
@@ -308,6 +891,56 @@ mod specification_support {
             }
         }
 
+        // Content is served from a git repository built from the commits,
+        // made one day apart, instead of the file system.
+        pub fn add_commit(mut self, message: &str, files: &[(&str, &str)]) -> Self {
+            self.commits.push(CommitOnServer {
+                message: message.to_string(),
+                files: files
+                    .iter()
+                    .map(|(target_path, content)| FileOnServer {
+                        target_path: target_path.to_string(),
+                        content: content.to_string(),
+                    })
+                    .collect(),
+            });
+            self
+        }
+
+        // Content is served from the repository instead of the file system;
+        // files added to the server are ignored.
+        pub fn with_repository(repository: InMemoryBlogRepository) -> Self {
+            BlogServer {
+                repository: Some(repository),
+                ..BlogServer::new()
+            }
+        }
+
+        // Files of the blog directory, next to the themes shipped with the
+        // engine, instead of serving templates and static files from the
+        // project.
+        pub fn add_blog_file(mut self, target_path: &str, content: &str) -> Self {
+            self.blog_files.push(FileOnServer {
+                target_path: target_path.to_string(),
+                content: content.to_string(),
+            });
+            self
+        }
+
+        // A blog directory without templates, themes or static files, so
+        // only what is built into the engine is available.
+        pub fn with_empty_blog_dir(mut self) -> Self {
+            self.empty_blog_dir = true;
+            self
+        }
+
+        // Requests `path` before the checked request and runs `change` after
+        // it, to see changes past what the first request cached.
+        pub fn after_request(mut self, path: &str, change: impl FnOnce() + Send + 'static) -> Self {
+            self.earlier_request = Some((path.to_string(), Box::new(change)));
+            self
+        }
+
         pub fn with_config(mut self, config_yaml: &str) -> Self {
             self.config = Some(config_yaml.to_string());
             self
@@ -317,37 +950,108 @@ mod specification_support {
             let temp_dir = TempDir::new().unwrap();
             let temp_path = temp_dir.path().to_owned();
 
-            for file_on_server in self.content_on_server {
-                let file_path = &file_on_server.target_path;
-                let file = temp_path.join(file_path);
-
-                if let Some(parent) = file.parent() {
-                    fs::create_dir_all(parent).unwrap();
-                }
-
-                fs::write(&file, &file_on_server.content).unwrap();
-            }
-
-            let blog_dir = BlogDir(".".into());
+            write_files(&temp_path, self.content_on_server);
+
+            let blog_temp_dir = TempDir::new().unwrap();
+            let blog_dir = if self.empty_blog_dir {
+                BlogDir(blog_temp_dir.path().into())
+            } else if self.blog_files.is_empty() {
+                BlogDir(".".into())
+            } else {
+                copy_dir(
+                    std::path::Path::new("themes"),
+                    &blog_temp_dir.path().join("themes"),
+                );
+                write_files(blog_temp_dir.path(), self.blog_files);
+                BlogDir(blog_temp_dir.path().into())
+            };
             let content_dir = ContentDir(temp_path.clone());
-            if let Some(config_content) = self.config {
-                fs::write(&content_dir.config_file(), &config_content).unwrap();
+            if let Some(config_content) = &self.config {
+                fs::write(content_dir.config_file(), config_content).unwrap();
             }
 
-            let app = create_app_with_dirs(temp_path, blog_dir.dir());
+            let mut commits = Vec::new();
+            let app = if let Some(repository) = self.repository {
+                let config = self
+                    .config
+                    .map(|yaml| BlogConfig::from_yaml_or_default(&yaml))
+                    .unwrap_or_default();
+                create_app_with_repository(repository, blog_dir.dir(), config)
+            } else if self.commits.is_empty() {
+                create_app_with_dirs(temp_path, blog_dir.dir())
+            } else {
+                commits = commit_all(&temp_path, self.commits);
+                create_app_from_git(temp_path, "HEAD", blog_dir.dir())
+                    .unwrap()
+                    .0
+            };
             let (server_addr, shutdown_tx, server_handle) = start_test_server(app).await;
 
-            RunningServer::new(server_addr, shutdown_tx, server_handle, temp_dir)
+            RunningServer::new(
+                server_addr,
+                shutdown_tx,
+                server_handle,
+                vec![temp_dir, blog_temp_dir],
+                commits,
+            )
         }
     }
 
+    type Assertion = Box<dyn FnOnce(&Response) + Send>;
+
     pub struct Obtained {
         server: BlogServer,
         path: String,
-        assertions: Vec<Box<dyn FnOnce(&Response) + Send>>,
+        headers: Vec<(String, String)>,
+        assertions: Vec<Assertion>,
     }
 
     impl Obtained {
+        pub fn with_header(mut self, name: &str, value: &str) -> Self {
+            self.headers.push((name.to_string(), value.to_string()));
+            self
+        }
+
+        pub fn expect_header(mut self, name: &str, expected: &str) -> Self {
+            let name = name.to_string();
+            let expected = expected.to_string();
+            self.assertions.push(Box::new(move |response| {
+                assert_eq!(
+                    response.headers.get(&name),
+                    Some(&expected),
+                    "Unexpected value for header '{}'",
+                    name
+                );
+            }));
+            self
+        }
+
+        pub fn expect_header_absent(mut self, name: &str) -> Self {
+            let name = name.to_string();
+            self.assertions.push(Box::new(move |response| {
+                assert!(
+                    !response.headers.contains_key(&name),
+                    "Expected no header '{}' in response, got {:?}",
+                    name,
+                    response.headers
+                );
+            }));
+            self
+        }
+
+        pub fn expect_header_present(mut self, name: &str) -> Self {
+            let name = name.to_string();
+            self.assertions.push(Box::new(move |response| {
+                assert!(
+                    response.headers.contains_key(&name),
+                    "Expected header '{}' in response, got {:?}",
+                    name,
+                    response.headers
+                );
+            }));
+            self
+        }
+
         pub fn expect_status_code(mut self, expected: u16) -> Self {
             self.assertions.push(Box::new(move |response| {
                 assert_eq!(
@@ -418,12 +1122,27 @@ mod specification_support {
             self
         }
 
-        pub async fn execute(self) {
+        pub async fn execute(mut self) {
+            let earlier_request = self.server.earlier_request.take();
             let server = self.server.start().await;
-            let http_response = server.get(&self.path).await;
+            if let Some((path, change)) = earlier_request {
+                server.get(&path, &[]).await;
+                change();
+            }
+            let http_response = server.get(&self.path, &self.headers).await;
 
             let response = Response {
                 status_code: http_response.status().as_u16(),
+                headers: http_response
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.to_string(),
+                            value.to_str().unwrap_or_default().to_string(),
+                        )
+                    })
+                    .collect(),
                 body: http_response
                     .text()
                     .await
@@ -443,7 +1162,9 @@ mod specification_support {
         server_addr: SocketAddr,
         shutdown_tx: Option<tokio::sync::oneshot::Sender<()>>,
         server_handle: Option<tokio::task::JoinHandle<()>>,
-        _temp_dir: TempDir,
+        _temp_dirs: Vec<TempDir>,
+        // Hashes of the commits, oldest first, for `{commit:N}` in paths.
+        commits: Vec<String>,
     }
 
     impl RunningServer {
@@ -451,19 +1172,35 @@ mod specification_support {
             server_addr: SocketAddr,
             shutdown_tx: tokio::sync::oneshot::Sender<()>,
             server_handle: tokio::task::JoinHandle<()>,
-            temp_dir: TempDir,
+            temp_dirs: Vec<TempDir>,
+            commits: Vec<String>,
         ) -> Self {
             RunningServer {
                 server_addr,
                 shutdown_tx: Some(shutdown_tx),
                 server_handle: Some(server_handle),
-                _temp_dir: temp_dir,
+                _temp_dirs: temp_dirs,
+                commits,
             }
         }
 
-        async fn get(&self, path: &str) -> reqwest::Response {
+        async fn get(&self, path: &str, headers: &[(String, String)]) -> reqwest::Response {
+            let path = self
+                .commits
+                .iter()
+                .enumerate()
+                .fold(path.to_string(), |path, (i, hash)| {
+                    path.replace(&format!("{{commit:{i}}}"), hash)
+                });
             let url = format!("http://{}{}", self.server_addr, path);
-            reqwest::Client::new().get(url).send().await.unwrap()
+            headers
+                .iter()
+                .fold(reqwest::Client::new().get(url), |request, (name, value)| {
+                    request.header(name, value)
+                })
+                .send()
+                .await
+                .unwrap()
         }
 
         fn shutdown_sync(&mut self) {
@@ -488,6 +1225,7 @@ mod specification_support {
     struct Response {
         body: String,
         status_code: u16,
+        headers: HashMap<String, String>,
     }
 
     struct FileOnServer {
@@ -495,8 +1233,73 @@ mod specification_support {
         target_path: String,
     }
 
+    struct CommitOnServer {
+        message: String,
+        files: Vec<FileOnServer>,
+    }
+
     // ===== 4. HELPER FUNCTIONS =====
 
+    fn write_files(dir: &std::path::Path, files: Vec<FileOnServer>) {
+        for file_on_server in files {
+            let file = dir.join(&file_on_server.target_path);
+
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+
+            fs::write(&file, &file_on_server.content).unwrap();
+        }
+    }
+
+    fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    fn commit_all(repo_path: &std::path::Path, commits: Vec<CommitOnServer>) -> Vec<String> {
+        const JAN_1_2024: i64 = 1_704_067_200;
+        let repo = Repository::init(repo_path).unwrap();
+        let mut hashes = Vec::new();
+        for (day, commit) in commits.into_iter().enumerate() {
+            let mut index = repo.index().unwrap();
+            for file in &commit.files {
+                let path = repo_path.join(&file.target_path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, &file.content).unwrap();
+                index
+                    .add_path(std::path::Path::new(&file.target_path))
+                    .unwrap();
+            }
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let time = Time::new(JAN_1_2024 + day as i64 * 86_400, 0);
+            let signature = Signature::new("Author", "author@example.com", &time).unwrap();
+            let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+            let parents: Vec<&git2::Commit> = parent.iter().collect();
+            let id = repo
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    &commit.message,
+                    &tree,
+                    &parents,
+                )
+                .unwrap();
+            hashes.push(id.to_string());
+        }
+        hashes
+    }
+
     async fn start_test_server(
         app: Router,
     ) -> (